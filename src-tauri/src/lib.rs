@@ -1,260 +1,5374 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 
 use std::{
-    fs::{create_dir_all, OpenOptions},
-    io::Write,
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fs::{create_dir_all, File, OpenOptions},
+    io::{BufReader, BufWriter, Write},
 };
 
-use tauri::Manager;
+use tauri::{Emitter, Manager};
 
-use crossbeam_channel::{unbounded, Sender};
-use rodio::{buffer::SamplesBuffer, Decoder, OutputStream, Sink, Source};
-use std::io::Cursor;
-// use std::time::{Duration, Instant};
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Select, SendError, Sender, TrySendError};
+use rodio::cpal::traits::HostTrait;
+use rodio::{buffer::SamplesBuffer, DeviceTrait, source::{Done, SeekError}, Decoder, OutputStream, OutputStreamHandle, PlayError, Sink, Source};
+use std::io::{Cursor, Read, Seek};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+mod synth;
 
 #[derive(Clone)]
 struct AudioTx(Sender<AudioMsg>);
 
+// Cleared when the audio thread exits for any reason (shutdown, no output device, panic),
+// so commands can say so instead of surfacing a raw channel error.
+static AUDIO_ALIVE: AtomicBool = AtomicBool::new(true);
+
+// Held by the audio thread; clears AUDIO_ALIVE when dropped, including on unwind.
+struct AudioAlive;
+
+impl Drop for AudioAlive {
+    fn drop(&mut self) {
+        AUDIO_ALIVE.store(false, Ordering::Relaxed);
+    }
+}
+
+fn audio_shut_down() -> AppError {
+    AppError::AudioShutDown("audio thread has shut down".to_string())
+}
+
+impl AudioTx {
+    fn send(&self, msg: AudioMsg) -> Result<(), AppError> {
+        if !AUDIO_ALIVE.load(Ordering::Relaxed) {
+            return Err(audio_shut_down());
+        }
+        self.0.send(msg).map_err(AppError::from)
+    }
+}
+
+// SFX get their own bounded queue to the audio thread: if it falls behind, new shots are
+// dropped (and counted in SFX_DROPPED) instead of piling up, and the invoke never blocks.
+// Everything else (BGM, settings) goes through AudioTx and is never dropped. The audio thread
+// takes from both queues in no particular order, so messages that must land after the shots
+// sent before them (stop, stop-all, mute, scheduling) use `SfxTx::send_ordered` instead.
+const SFX_QUEUE_CAP: usize = 64;
+static SFX_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+// Failure counters for `audio_stats`, since launch. Atomics so sources running on rodio's
+// output thread (`FileLoop`) can count too.
+static DECODE_FAILURES: AtomicU64 = AtomicU64::new(0);
+static SINK_FAILURES: AtomicU64 = AtomicU64::new(0);
+static DEVICE_REBUILDS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone)]
+struct SfxTx(Sender<AudioMsg>);
+
+impl SfxTx {
+    // Queue `msg` carrying `shots` sounds. False if the queue was full and they were dropped.
+    fn send(&self, msg: AudioMsg, shots: u64) -> Result<bool, AppError> {
+        if !AUDIO_ALIVE.load(Ordering::Relaxed) {
+            return Err(audio_shut_down());
+        }
+        match self.0.try_send(msg) {
+            Ok(()) => Ok(true),
+            Err(TrySendError::Full(_)) => {
+                SFX_DROPPED.fetch_add(shots, Ordering::Relaxed);
+                Ok(false)
+            }
+            Err(TrySendError::Disconnected(msg)) => Err(SendError(msg).into()),
+        }
+    }
+
+    // Queue `msg` behind the shots already waiting, never dropping it. Blocks only while the
+    // queue is full, which the audio thread clears in one pass.
+    fn send_ordered(&self, msg: AudioMsg) -> Result<(), AppError> {
+        if !AUDIO_ALIVE.load(Ordering::Relaxed) {
+            return Err(audio_shut_down());
+        }
+        self.0.send(msg).map_err(AppError::from)
+    }
+}
+
+// Join handle of the running audio thread, so `audio_shutdown` can wait for it to exit.
+struct AudioThread(Mutex<Option<JoinHandle<()>>>);
+
+// Whether BGM pauses while the window is unfocused; from settings at startup, then
+// `set_pause_on_blur`.
+#[derive(Default)]
+struct PauseOnBlur(AtomicBool);
+
+// What every command fails with. Serialized as {"kind": "io", "message": "..."} so the
+// frontend can branch on `kind` and still show `message`.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+enum AppError {
+    // Reading or writing a file.
+    Io(String),
+    // The audio thread is gone or didn't answer.
+    AudioSend(String),
+    // The audio thread has exited (e.g. after `audio_shutdown`); audio commands do nothing.
+    AudioShutDown(String),
+    // The log writer is gone or didn't answer.
+    LogSend(String),
+    // Audio, JSON, TOML or replay data that couldn't be read as such.
+    Decode(String),
+    // The app data dir, or a user-supplied path, couldn't be resolved.
+    PathResolve(String),
+    // A bad argument, or a call that doesn't fit the current state.
+    InvalidInput(String),
+    // Window management and capture.
+    Window(String),
+    // The system audio host or an output device couldn't be queried or opened.
+    Device(String),
+    // Something this source, platform or build can't do (e.g. seeking a stream that can't seek).
+    Unsupported(String),
+    // Poisoned locks, panicked threads and other bugs.
+    Internal(String),
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Io(m)
+            | AppError::AudioSend(m)
+            | AppError::AudioShutDown(m)
+            | AppError::LogSend(m)
+            | AppError::Decode(m)
+            | AppError::PathResolve(m)
+            | AppError::InvalidInput(m)
+            | AppError::Window(m)
+            | AppError::Device(m)
+            | AppError::Unsupported(m)
+            | AppError::Internal(m) => f.write_str(m),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+// For callers that still want the old plain-string errors.
+impl From<AppError> for String {
+    fn from(e: AppError) -> Self {
+        e.to_string()
+    }
+}
+
+impl From<crossbeam_channel::SendError<AudioMsg>> for AppError {
+    fn from(e: crossbeam_channel::SendError<AudioMsg>) -> Self {
+        // The receiver only goes away with the audio thread.
+        if !AUDIO_ALIVE.load(Ordering::Relaxed) {
+            return audio_shut_down();
+        }
+        AppError::AudioSend(format!("send: {e}"))
+    }
+}
+
+impl From<crossbeam_channel::SendError<LogMsg>> for AppError {
+    fn from(e: crossbeam_channel::SendError<LogMsg>) -> Self {
+        AppError::LogSend(format!("log: {e}"))
+    }
+}
+
+impl<T> From<std::sync::PoisonError<T>> for AppError {
+    fn from(e: std::sync::PoisonError<T>) -> Self {
+        AppError::Internal(format!("lock: {e}"))
+    }
+}
+
 #[derive(Debug)]
 enum AudioMsg {
-    Sfx { kind: String, volume: f32 },
-    BgmPlay { volume: f32 },
-    BgmStop,
+    Sfx {
+        id: u64,
+        kind: String,
+        volume: f32,
+        pitch: f32,
+        pitch_range: Option<(f32, f32)>,
+        pan: f32,
+        variant: u32,
+        echo: Option<Echo>,
+        sample: Option<u32>,
+        distance: f32,
+    },
+    SfxBatch { shots: Vec<(u64, SfxEvent)> },
+    SfxStop { id: u64 },
+    SfxStopAll,
+    SfxCooldown { kind: String, ms: u64 },
+    SfxFile { id: u64, clip: AudioClip, volume: f32 },
+    Synth { id: u64, spec: synth::SynthSpec, volume: f32 },
+    // `volume` None keeps the current BGM volume.
+    BgmPlay { track: String, volume: Option<f32>, fade: Duration, loop_points: Option<(u64, u64)>, looping: bool },
+    BgmStop { fade: Duration },
+    // Fade everything out, then stop and end the thread (app quit).
+    FadeOutAll { fade: Duration },
+    BgmPause,
+    BgmResume,
+    // Window focus changed. Losing it pauses playing BGM; regaining it resumes only what the
+    // blur paused.
+    WindowFocus { focused: bool },
+    BgmPlayFile { path: PathBuf, volume: f32 },
     BgmVolume { volume: f32 },
+    BgmMuted { muted: bool },
+    Tuning { tuning: AudioTuning },
+    BgmCrossfade { track: String, volume: f32, duration: Duration },
+    BgmSwitchOnBeat { track: String, bpm: f32, fade: Duration },
+    SetBus { bus: Bus, volume: f32 },
+    ApplyConfig { config: AudioConfig },
+    Shutdown,
+    SetDevice { name: String },
+    QueryBgmPosition { reply: Sender<Duration> },
+    QueryBgm { reply: Sender<bool> },
+    QueryState { reply: Sender<AudioState> },
+    QueryStats { reply: Sender<AudioStats> },
+    BgmSpeed { factor: f32 },
+    BgmSeek { pos: Duration, reply: Sender<Result<(), AppError>> },
+    Lowpass { filter: Option<LowpassFilter> },
+    SetMuted { muted: bool },
+    PerceptualVolume { enabled: bool },
+    PreviewSfx { kind: String, volume: f32 },
+    TestTone { freq: f32, duration: Duration, volume: f32 },
+    Warmup,
+    BoostLoopStart,
+    BoostLoopSet { speed: f32 },
+    BoostLoopStop,
+    BgmPreview { track: String, volume: f32 },
+    BgmPreviewStop,
+    AmbientPlay { track: String, volume: f32 },
+    AmbientStop,
+    SfxLimiter { enabled: bool },
+    SfxDuck { amount: f32 },
+    // Pause-menu transition (`audio_enter_pause` / `audio_exit_pause`).
+    EnterPause,
+    ExitPause,
+    ScheduleSfx { id: u64, kind: String, at: Instant, volume: f32 },
+    CancelScheduledSfx,
+    // From `device_watch_thread`: `name` is no longer listed.
+    DeviceLost { name: String },
 }
 
-fn bgm_bytes() -> &'static [u8] {
-    include_bytes!("../../assets/music/bgm.ogg")
+// Mixer buses. Per-call volumes are scaled by their bus and then by master.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Bus {
+    Master,
+    Sfx,
+    Bgm,
 }
 
-fn sfx_bytes(kind: &str) -> Option<&'static [u8]> {
-    match kind {
-        "ui" => Some(include_bytes!("../../public/sfx/ui.wav")),
-        "eat" => Some(include_bytes!("../../public/sfx/eat.wav")),
-        "boost" => Some(include_bytes!("../../public/sfx/boost.wav")),
-        "dash" => Some(include_bytes!("../../public/sfx/dash.wav")),
-        "shield" => Some(include_bytes!("../../public/sfx/shield.wav")),
-        "poison" => Some(include_bytes!("../../public/sfx/poison.wav")),
-        "death" => Some(include_bytes!("../../public/sfx/death.wav")),
-        _ => None,
+#[derive(Debug, Clone, Copy)]
+struct BusVolumes {
+    master: f32,
+    sfx: f32,
+    bgm: f32,
+    // Map slider values through `perceptual_gain`; off (the default) = linear gains.
+    perceptual: bool,
+}
+
+// A volume from the frontend as a usable gain: NaN and infinities (e.g. a bad division) become
+// 0.0 rather than slipping past `clamp` into `set_volume`, and negatives floor at 0. Callers
+// cap the top end where there is one.
+fn sanitize_gain(v: f32) -> f32 {
+    if v.is_finite() { v.max(0.0) } else { 0.0 }
+}
+
+// Slider value (0..1) to gain on a square-law curve, so equal slider steps sound like roughly
+// equal loudness steps (0.5 is about -12dB rather than -6dB). Above 1.0 (SFX boost) it stays
+// linear so the extra headroom doesn't balloon.
+fn perceptual_gain(v: f32) -> f32 {
+    let v = v.max(0.0);
+    if v <= 1.0 { v * v } else { v }
+}
+
+impl BusVolumes {
+    fn curve(&self, v: f32) -> f32 {
+        if self.perceptual { perceptual_gain(v) } else { v }
+    }
+
+    fn sfx_gain(&self, volume: f32) -> f32 {
+        self.curve(volume * self.sfx * self.master)
+    }
+
+    fn bgm_gain(&self, volume: f32) -> f32 {
+        self.curve(volume * self.bgm * self.master)
+    }
+
+    // The ambient loop has no bus of its own; it sits under master with everything else.
+    fn ambient_gain(&self, volume: f32) -> f32 {
+        self.curve(volume * self.master)
+    }
+
+    // "ui" sounds follow master only, so turning gameplay SFX down never silences menus.
+    fn ui_gain(&self, volume: f32) -> f32 {
+        self.curve(volume * self.master)
     }
 }
 
-fn enemy_pickup_source() -> SamplesBuffer<f32> {
-    // Procedural rival pickup sound: cyber "chirp" + sub click.
-    // 48kHz mono.
-    let sr: u32 = 48_000;
-    let dur_s = 0.14_f32;
-    let n = (dur_s * sr as f32) as usize;
-    let mut out = Vec::with_capacity(n);
+#[derive(Debug, Clone, serde::Serialize)]
+struct AudioErrorPayload {
+    context: String,
+    message: String,
+}
 
-    for i in 0..n {
-        let t = i as f32 / sr as f32;
+// Where audio-thread failures go: stderr, plus an "audio-error" event so the UI can show that
+// sound is unavailable. No app handle (e.g. before the app exists) means stderr only.
+#[derive(Clone)]
+struct AudioEvents(Option<tauri::AppHandle>);
 
-        // envelope (fast attack, quick decay)
-        let env = if t < 0.01 { t / 0.01 } else { ((dur_s - t) / (dur_s - 0.01)).max(0.0) };
-        let env = env * env;
+impl AudioEvents {
+    fn error(&self, context: &str, message: impl std::fmt::Display) {
+        let message = message.to_string();
+        eprintln!("{context} error: {message}");
+        self.emit("audio-error", AudioErrorPayload { context: context.to_string(), message });
+    }
 
-        // downward chirp
-        let f0 = 820.0;
-        let f1 = 260.0;
-        let ft = f0 + (f1 - f0) * (t / dur_s);
-        let phase = 2.0 * std::f32::consts::PI * ft * t;
-        let chirp = phase.sin();
+    fn emit<S: serde::Serialize + Clone>(&self, event: &str, payload: S) {
+        if let Some(app) = &self.0 {
+            let _ = app.emit(event, payload);
+        }
+    }
+}
 
-        // add a short sub click at the start
-        let sub = if t < 0.03 {
-            (2.0 * std::f32::consts::PI * 72.0 * t).sin() * (1.0 - t / 0.03)
-        } else {
-            0.0
-        };
+// Snapshot returned by `audio_state`, for bug reports.
+#[derive(Debug, Clone, serde::Serialize)]
+struct AudioState {
+    master_volume: f32,
+    sfx_volume: f32,
+    bgm_volume: f32,
+    muted: bool,
+    // Track name, or the file path for user BGM; None when nothing is loaded.
+    bgm_track: Option<String>,
+    bgm_playing: bool,
+    bgm_paused: bool,
+    active_sfx: usize,
+    // SFX dropped because the audio thread fell behind, since launch.
+    dropped_sfx: u64,
+    // Output device the engine actually opened (None if unknown), and its sample rate.
+    device: Option<String>,
+    sample_rate: u32,
+}
 
-        // slight "digital" edge
-        let edge = (chirp * 1.35).tanh();
+// Returned by `audio_stats`: failure counts since launch, for "crackly audio" reports.
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+struct AudioStats {
+    decode_failures: u64,
+    sink_failures: u64,
+    dropped_sfx: u64,
+    // Times the watchdog reopened the output after the device was lost.
+    device_rebuilds: u64,
+}
 
-        out.push((edge * 0.75 + sub * 0.45) * env);
+impl AudioStats {
+    fn now() -> AudioStats {
+        AudioStats {
+            decode_failures: DECODE_FAILURES.load(Ordering::Relaxed),
+            sink_failures: SINK_FAILURES.load(Ordering::Relaxed),
+            dropped_sfx: SFX_DROPPED.load(Ordering::Relaxed),
+            device_rebuilds: DEVICE_REBUILDS.load(Ordering::Relaxed),
+        }
     }
+}
 
-    SamplesBuffer::new(1, sr, out)
+// Payload of "audio-device-lost" / "audio-device-recovered".
+#[derive(Debug, Clone, serde::Serialize)]
+struct AudioDevicePayload {
+    device: Option<String>,
+    reason: Option<String>,
 }
 
-#[tauri::command]
-fn greet(name: &str) -> String {
-    format!("Hello, {}! You've been greeted from Rust!", name)
+// Payload of "bgm-started" (the track's first samples are playing) and "bgm-finished" (a
+// one-shot track just ended).
+#[derive(Debug, Clone, serde::Serialize)]
+struct BgmTrackPayload {
+    track: String,
 }
 
-#[tauri::command]
-fn append_log(app: tauri::AppHandle, lines: Vec<String>) -> Result<(), String> {
-    let dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("app_data_dir: {e}"))?;
+// Payload of "audio-level": RMS and peak of recent output, both 0..1-ish (can exceed 1 when
+// loud sounds overlap).
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+struct AudioLevelPayload {
+    rms: f32,
+    peak: f32,
+}
 
-    create_dir_all(&dir).map_err(|e| format!("create_dir_all: {e}"))?;
-    let path = dir.join("ultimate-snake.log");
+// How often "audio-level" is emitted while anything is playing (~30Hz).
+const LEVEL_INTERVAL: Duration = Duration::from_millis(33);
+// Samples a LevelTap accumulates before taking the meter's lock.
+const LEVEL_FLUSH: u32 = 256;
+
+#[derive(Default)]
+struct LevelAcc {
+    sum_sq: f64,
+    peak: f32,
+    n: u64,
+}
 
-    let mut f = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&path)
-        .map_err(|e| format!("open log: {e}"))?;
+// Levels of the samples the mixer has pulled from tapped sources since the last `take`.
+#[derive(Default)]
+struct LevelMeter(Mutex<LevelAcc>);
 
-    for line in lines {
-        writeln!(f, "{}", line).map_err(|e| format!("write log: {e}"))?;
+impl LevelMeter {
+    fn add(&self, sum_sq: f32, peak: f32, n: u32) {
+        if let Ok(mut acc) = self.0.lock() {
+            acc.sum_sq += sum_sq as f64;
+            acc.peak = acc.peak.max(peak);
+            acc.n += n as u64;
+        }
     }
 
-    Ok(())
+    // (mean square, peak) since the last call; zeros if nothing was pulled.
+    fn take(&self) -> (f32, f32) {
+        let Ok(mut acc) = self.0.lock() else { return (0.0, 0.0) };
+        let acc = std::mem::take(&mut *acc);
+        if acc.n == 0 {
+            return (0.0, 0.0);
+        }
+        ((acc.sum_sq / acc.n as f64) as f32, acc.peak)
+    }
 }
 
-#[tauri::command]
-fn play_sfx(state: tauri::State<'_, AudioTx>, kind: String, volume: f32, muted: bool) -> Result<(), String> {
-    if muted || volume <= 0.0001 {
-        return Ok(());
+// Accessibility downmix (`set_mono_output`): every frame of BGM and SFX is replaced by the
+// average of its channels, so panned sounds end up centered. Read per frame, so it also
+// applies to sounds already playing.
+static MONO_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+struct MonoMix<S> {
+    inner: S,
+    frame: Vec<f32>,
+    pos: usize,
+}
+
+impl<S> MonoMix<S> {
+    fn new(inner: S) -> Self {
+        Self { inner, frame: Vec::new(), pos: 0 }
     }
-    // send to audio thread (which owns OutputStream)
-    state
-        .0
-        .send(AudioMsg::Sfx { kind, volume: volume.clamp(0.0, 1.5) })
-        .map_err(|e| format!("send: {e}"))
 }
 
+impl<S: Source<Item = f32>> Iterator for MonoMix<S> {
+    type Item = f32;
 
-#[tauri::command]
-fn bgm_play(state: tauri::State<'_, AudioTx>, volume: f32, muted: bool) -> Result<(), String> {
-    if muted || volume <= 0.0001 {
-        return Ok(());
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.frame.len() {
+            let channels = self.inner.channels().max(1) as usize;
+            if channels == 1 {
+                return self.inner.next();
+            }
+            self.frame.clear();
+            self.frame.extend(self.inner.by_ref().take(channels));
+            self.pos = 0;
+            if self.frame.is_empty() {
+                return None;
+            }
+            if MONO_OUTPUT.load(Ordering::Relaxed) {
+                let avg = self.frame.iter().sum::<f32>() / self.frame.len() as f32;
+                self.frame.fill(avg);
+            }
+        }
+        self.pos += 1;
+        Some(self.frame[self.pos - 1])
     }
-    state.0.send(AudioMsg::BgmPlay { volume: volume.clamp(0.0, 1.0) }).map_err(|e| format!("send: {e}"))
 }
 
-#[tauri::command]
-fn bgm_stop(state: tauri::State<'_, AudioTx>) -> Result<(), String> {
-    state.0.send(AudioMsg::BgmStop).map_err(|e| format!("send: {e}"))
+impl<S: Source<Item = f32>> Source for MonoMix<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.frame.clear();
+        self.pos = 0;
+        self.inner.try_seek(pos)
+    }
 }
 
-#[tauri::command]
-fn bgm_volume(state: tauri::State<'_, AudioTx>, volume: f32, muted: bool) -> Result<(), String> {
-    if muted {
-        state.0.send(AudioMsg::BgmStop).map_err(|e| format!("send: {e}"))?;
-        return Ok(());
+// Passes samples through unchanged while feeding their levels to a LevelMeter.
+struct LevelTap<S> {
+    inner: S,
+    meter: Arc<LevelMeter>,
+    sum_sq: f32,
+    peak: f32,
+    n: u32,
+}
+
+impl<S> LevelTap<S> {
+    fn new(inner: S, meter: Arc<LevelMeter>) -> Self {
+        Self { inner, meter, sum_sq: 0.0, peak: 0.0, n: 0 }
+    }
+
+    fn flush(&mut self) {
+        if self.n > 0 {
+            self.meter.add(self.sum_sq, self.peak, self.n);
+            self.sum_sq = 0.0;
+            self.peak = 0.0;
+            self.n = 0;
+        }
     }
-    state.0.send(AudioMsg::BgmVolume { volume: volume.clamp(0.0, 1.0) }).map_err(|e| format!("send: {e}"))
 }
 
-#[tauri::command]
-fn log_path(app: tauri::AppHandle) -> Result<String, String> {
-    let dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("app_data_dir: {e}"))?;
-    Ok(dir.join("ultimate-snake.log").to_string_lossy().to_string())
+impl<S: Source<Item = f32>> Iterator for LevelTap<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let Some(s) = self.inner.next() else {
+            self.flush();
+            return None;
+        };
+        self.sum_sq += s * s;
+        self.peak = self.peak.max(s.abs());
+        self.n += 1;
+        if self.n >= LEVEL_FLUSH {
+            self.flush();
+        }
+        Some(s)
+    }
 }
 
-// Returns:
-// - Ok(Some(msg)) when a message is received
-// - Ok(None) when we timed out (used to wake up and restore BGM after duck)
-// - Err(()) when channel is disconnected
-// (kept around in case we re-introduce timed audio events later)
-// fn recv_with_duck_wakeup(...) { ... }
+impl<S: Source<Item = f32>> Source for LevelTap<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    // Audio thread: owns OutputStream so we avoid Send/Sync issues.
-    let (tx, rx) = unbounded::<AudioMsg>();
-    std::thread::spawn(move || {
-        let (_stream, handle) = match OutputStream::try_default() {
-            Ok(v) => v,
-            Err(e) => {
-                eprintln!("audio OutputStream error: {e}");
-                return;
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+// Number of persistent SFX sinks in total.
+const SFX_POOL_SIZE: usize = 8;
+// How many of those are reserved for "ui", so menu clicks never queue behind gameplay sounds;
+// the rest are shared by everything else. A sink plays what's appended to it one after
+// another, so a single one would make key-repeat clicks wait behind each other; with three,
+// each click (ui.wav is 100ms) finds an idle one at key-repeat rates (~30/s).
+const UI_VOICES: usize = 3;
+
+// Fixed set of sinks so overlapping SFX actually play at the same time instead of
+// queueing on a single sink. Sinks are created once (avoids per-sound sink creation
+// overhead + ALSA underruns).
+struct SfxPool {
+    ui: Vec<Sink>,
+    ui_next: usize,
+    voices: Vec<Sink>,
+    next: usize,
+}
+
+impl SfxPool {
+    fn new(backend: &dyn AudioBackend) -> Result<Self, PlayError> {
+        let ui = (0..UI_VOICES).map(|_| backend.new_sink()).collect::<Result<_, _>>()?;
+        let voices = (0..SFX_POOL_SIZE - UI_VOICES).map(|_| backend.new_sink()).collect::<Result<_, _>>()?;
+        Ok(Self { ui, ui_next: 0, voices, next: 0 })
+    }
+
+    // Push one silent frame through every sink so the device pipeline is already running when
+    // the first real sound arrives.
+    fn warm_up(&self, sample_rate: u32) {
+        for s in self.ui.iter().chain(&self.voices) {
+            s.append(SamplesBuffer::new(2, sample_rate, vec![0.0f32; 2]));
+        }
+    }
+
+    // The "ui" sinks and the gameplay voices are scaled separately so menu ducking can leave
+    // menu clicks alone.
+    fn set_volume(&self, ui: f32, gameplay: f32) {
+        for s in &self.ui {
+            s.set_volume(ui);
+        }
+        for s in &self.voices {
+            s.set_volume(gameplay);
+        }
+    }
+
+    fn stop_all(&self) {
+        for s in self.ui.iter().chain(&self.voices) {
+            s.stop();
+        }
+    }
+
+    // First idle voice for `kind` ("ui" or gameplay), or round-robin when every one is busy.
+    fn pick(&mut self, kind: &str) -> &Sink {
+        let (sinks, next) = if kind == "ui" { (&self.ui, &mut self.ui_next) } else { (&self.voices, &mut self.next) };
+        let i = match sinks.iter().position(|s| s.empty()) {
+            Some(i) => i,
+            None => {
+                let i = *next;
+                *next = (*next + 1) % sinks.len();
+                i
             }
         };
+        &sinks[i]
+    }
+}
 
-        let mut bgm: Option<Sink> = None;
-        let mut bgm_vol: f32 = 0.45;
+// Minimum gap between two plays of the same kind; repeats inside the window are dropped so
+// combo spam can't flood the sinks. "ui" has none so menus stay responsive.
+const DEFAULT_SFX_COOLDOWN_MS: u64 = 30;
+const SFX_COOLDOWNS_MS: &[(&str, u64)] = &[
+    ("ui", 0),
+    ("eat", 30),
+    ("enemy_pickup", 30),
+    ("boost", 40),
+    ("dash", 40),
+    ("shield", 60),
+    ("poison", 60),
+    ("death", 120),
+];
 
-        // One persistent SFX sink (reduces ALSA underruns + avoids per-sound sink creation overhead)
-        let sfx_sink = match Sink::try_new(&handle) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("sfx Sink error: {e}");
-                return;
+// Upper bound for `set_sfx_cooldown`.
+const SFX_COOLDOWN_MAX_MS: u32 = 10_000;
+
+// Cooldown for `kind`: a `set_sfx_cooldown` override, else a config.toml one, else the table
+// above.
+fn sfx_cooldown(tuning: &AudioTuning, overrides: &HashMap<String, u64>, kind: &str) -> Duration {
+    let ms = overrides.get(kind).or_else(|| tuning.sfx_cooldowns_ms.get(kind)).copied().unwrap_or_else(|| {
+        SFX_COOLDOWNS_MS
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, ms)| *ms)
+            .unwrap_or(tuning.sfx_cooldown_ms)
+    });
+    Duration::from_millis(ms)
+}
+
+// Loudness trims per kind, multiplied into the requested volume before it's clamped, so
+// hot and quiet assets can be balanced without re-mastering. To tune: play the kinds side by
+// side at the same slider value and lower the loud one (0.5 is about -6dB) or raise the quiet
+// one. Kinds not listed use 1.0.
+const SFX_TRIMS: &[(&str, f32)] = &[
+    ("ui", 1.0),
+    ("eat", 1.0),
+    ("enemy_pickup", 1.0),
+    ("boost", 1.0),
+    ("dash", 1.0),
+    ("shield", 1.0),
+    ("poison", 1.0),
+    ("death", 1.0),
+];
+
+fn sfx_trim(kind: &str) -> f32 {
+    SFX_TRIMS.iter().find(|(k, _)| *k == kind).map(|(_, t)| *t).unwrap_or(1.0)
+}
+
+// Soft limiter knee: samples below it pass untouched (so transients keep their punch); above
+// it they're squashed with tanh toward, but never past, full scale.
+const SFX_LIMIT_KNEE: f32 = 0.7;
+
+fn soft_limit(x: f32) -> f32 {
+    let a = x.abs();
+    if a <= SFX_LIMIT_KNEE {
+        return x;
+    }
+    let room = 1.0 - SFX_LIMIT_KNEE;
+    (SFX_LIMIT_KNEE + room * ((a - SFX_LIMIT_KNEE) / room).tanh()).copysign(x)
+}
+
+// Applies `soft_limit` to a source when enabled. rodio sums the pool sinks itself, so this
+// works per voice: it tames boosted/hot sounds before they hit the mixer.
+struct SoftLimit<S> {
+    inner: S,
+    enabled: bool,
+}
+
+impl<S: Source<Item = f32>> Iterator for SoftLimit<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let s = self.inner.next()?;
+        Some(if self.enabled { soft_limit(s) } else { s })
+    }
+}
+
+impl<S: Source<Item = f32>> Source for SoftLimit<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+// Ids handed out by `play_sfx` so callers can stop a specific sound later. 0 is never used
+// (it means "nothing was played").
+static NEXT_SFX_ID: AtomicU64 = AtomicU64::new(1);
+
+// Control for one in-flight SFX. Pool sinks are shared between sounds, so a single sound
+// can't be stopped through its sink; instead the source polls a stop flag, and `Done`
+// tells us when it has finished so the entry can be dropped.
+struct SfxVoice {
+    stop: Arc<AtomicBool>,
+    remaining: Arc<AtomicUsize>,
+}
+
+impl SfxVoice {
+    fn wrap<S>(src: S) -> (Self, impl Source<Item = f32> + Send + 'static)
+    where
+        S: Source<Item = f32> + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let remaining = Arc::new(AtomicUsize::new(1));
+        let flag = stop.clone();
+        let src = src.stoppable().periodic_access(Duration::from_millis(5), move |s| {
+            if flag.load(Ordering::Relaxed) {
+                s.stop();
             }
-        };
-        sfx_sink.set_volume(1.0);
+        });
+        (Self { stop, remaining: remaining.clone() }, Done::new(src, remaining))
+    }
 
-        loop {
-            let msg = match rx.recv() {
-                Ok(m) => m,
-                Err(_) => break,
-            };
+    fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
 
-            match msg {
-                AudioMsg::Sfx { kind, volume } => {
-                    // Same volume behavior for you + rival.
-                    let amp = volume.clamp(0.0, 2.0);
+    fn finished(&self) -> bool {
+        self.remaining.load(Ordering::Relaxed) == 0
+    }
+}
 
-                    if kind == "enemy_pickup" {
-                        let src = enemy_pickup_source();
-                        sfx_sink.append(src.amplify(amp));
-                        continue;
-                    }
+// Playback-speed limits for SFX pitch; beyond this the samples turn to mush.
+const SFX_PITCH_MIN: f32 = 0.5;
+const SFX_PITCH_MAX: f32 = 2.0;
 
-                    let bytes = match sfx_bytes(&kind) {
-                        Some(b) => b,
-                        None => continue,
-                    };
-
-                    let cur = Cursor::new(bytes);
-                    let src = match Decoder::new(cur) {
-                        Ok(s) => s,
-                        Err(e) => {
-                            eprintln!("audio Decoder error: {e}");
-                            continue;
-                        }
-                    };
-
-                    sfx_sink.append(src.amplify(amp));
-                }
-                AudioMsg::BgmPlay { volume } => {
-                    bgm_vol = volume;
-                    if bgm.is_none() {
-                        let bytes = bgm_bytes();
-                        let sink = match Sink::try_new(&handle) {
-                            Ok(s) => s,
-                            Err(e) => { eprintln!("bgm Sink error: {e}"); continue; }
-                        };
-                        sink.set_volume(bgm_vol);
-                        let cur = Cursor::new(bytes);
-                        let src = match Decoder::new(cur) {
-                            Ok(s) => s,
-                            Err(e) => { eprintln!("bgm Decoder error: {e}"); continue; }
-                        };
-                        sink.append(src.repeat_infinite());
-                        bgm = Some(sink);
-                    } else if let Some(s) = &bgm {
-                        s.set_volume(volume);
-                    }
-                }
-                AudioMsg::BgmVolume { volume } => {
-                    bgm_vol = volume;
-                    if let Some(s) = &bgm {
-                        s.set_volume(bgm_vol);
-                    }
-                }
-                AudioMsg::BgmStop => {
-                    if let Some(s) = bgm.take() {
-                        s.stop();
-                    }
-                }
+// Tiny xorshift64 PRNG (no extra dependency; only used for audio variation).
+struct Rng(u64);
+
+impl Rng {
+    fn seeded(seed: u64) -> Self {
+        // splitmix the seed so small/adjacent seeds (variant 1, 2, ...) still diverge quickly;
+        // xorshift must never hold 0
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self((z ^ (z >> 31)).max(1))
+    }
+
+    fn from_time() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x9E37_79B9_7F4A_7C15);
+        Self::seeded(nanos)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    // Uniform in [0, 1).
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn range(&mut self, lo: f32, hi: f32) -> f32 {
+        lo + (hi - lo) * self.next_f32()
+    }
+}
+
+// Track `bgm_play` uses when the caller doesn't name one.
+const DEFAULT_BGM_TRACK: &str = "gameplay";
+
+// Embedded BGM tracks by name. "menu"/"gameplay"/"boss" are the in-game contexts; track1..4
+// are the tracks the web build offers in its music picker (menu and boss reuse two of them).
+fn bgm_track_bytes(name: &str) -> Option<&'static [u8]> {
+    match name {
+        "gameplay" => Some(include_bytes!("../../assets/music/bgm.ogg")),
+        "menu" | "track1" => Some(include_bytes!("../../public/music/track1.mp3")),
+        "track2" => Some(include_bytes!("../../public/music/track2.mp3")),
+        "boss" | "track3" => Some(include_bytes!("../../public/music/track3.mp3")),
+        "track4" => Some(include_bytes!("../../public/music/track4.mp3")),
+        _ => None,
+    }
+}
+
+// One name per embedded BGM asset (aliases like "menu"/"boss" share these files).
+const BGM_TRACKS: &[&str] = &["gameplay", "track1", "track2", "track3", "track4"];
+
+// Loudness trims per BGM asset, so tracks mastered at different levels play at the same
+// perceived volume; `bgm_play`'s volume then scales relative to this. A linear gain on top of
+// the bus volume (0.5 is about -6dB). Aliases use their asset's trim; unlisted = 1.0.
+const BGM_TRIMS: &[(&str, f32)] = &[
+    ("gameplay", 1.0),
+    ("track1", 1.0),
+    ("track2", 1.0),
+    ("track3", 1.0),
+    ("track4", 1.0),
+];
+
+fn bgm_trim(name: &str) -> f32 {
+    let asset = match name {
+        "menu" => "track1",
+        "boss" => "track3",
+        other => other,
+    };
+    BGM_TRIMS.iter().find(|(k, _)| *k == asset).map(|(_, t)| *t).unwrap_or(1.0)
+}
+
+// Every kind `sfx_bytes` knows about; used to warm the decoded-SFX cache at thread start.
+const SFX_KINDS: &[&str] = &["ui", "eat", "boost", "dash", "shield", "poison", "death"];
+
+// Embedded samples for each kind. A kind with several samples picks one at random per shot;
+// unknown kinds get an empty slice.
+fn sfx_bytes(kind: &str) -> &'static [&'static [u8]] {
+    match kind {
+        "ui" => &[include_bytes!("../../public/sfx/ui.wav")],
+        "eat" => &[include_bytes!("../../public/sfx/eat.wav")],
+        "boost" => &[include_bytes!("../../public/sfx/boost.wav")],
+        "dash" => &[include_bytes!("../../public/sfx/dash.wav")],
+        "shield" => &[include_bytes!("../../public/sfx/shield.wav")],
+        "poison" => &[include_bytes!("../../public/sfx/poison.wav")],
+        "death" => &[include_bytes!("../../public/sfx/death.wav")],
+        _ => &[],
+    }
+}
+
+// Decode every sample of `kind`, reporting (and skipping) any that fail. Each is brought to
+// the SFX layout and gets the SFX_EDGE_FADE so hard-edged WAVs don't click.
+fn decode_sfx_kind(kind: &str, events: &AudioEvents) -> Vec<AudioClip> {
+    sfx_bytes(kind)
+        .iter()
+        .filter_map(|bytes| match decode_buffer(bytes) {
+            Ok(clip) => Some(clip.into_sfx_layout().with_edge_fades(SFX_EDGE_FADE)),
+            Err(e) => {
+                DECODE_FAILURES.fetch_add(1, Ordering::Relaxed);
+                events.error("audio Decoder", format!("{kind}: {e}"));
+                None
             }
+        })
+        .collect()
+}
+
+// First sample of `kind` as the engine would queue it (decoded, edge-faded, amplified by
+// `amp`), without a device. None for unknown kinds or undecodable samples.
+#[cfg(test)]
+fn decode_sfx(kind: &str, amp: f32) -> Option<Vec<f32>> {
+    let clip = decode_sfx_kind(kind, &AudioEvents(None)).into_iter().next()?;
+    Some(clip.into_source().amplify(amp).collect())
+}
+
+// Try to decode every embedded asset: SFX fully, BGM tracks up to their first sample (their
+// headers and first frame; fully decoding every track would take seconds). Returns
+// ("sfx:<kind>" / "bgm:<track>", ok) for each, logging failures to stderr. Kinds with several
+// samples list each as "sfx:<kind>[i]".
+fn verify_audio_assets() -> Vec<(String, bool)> {
+    let mut out = Vec::new();
+    let mut record = |name: String, res: Result<(), String>| {
+        if let Err(e) = &res {
+            eprintln!("audio asset {name} invalid: {e}");
         }
-    });
+        out.push((name, res.is_ok()));
+    };
+    for kind in SFX_KINDS {
+        let samples = sfx_bytes(kind);
+        for (i, bytes) in samples.iter().enumerate() {
+            let name = if samples.len() > 1 { format!("sfx:{kind}[{i}]") } else { format!("sfx:{kind}") };
+            record(name, decode_buffer(bytes).map(|_| ()).map_err(|e| e.to_string()));
+        }
+    }
+    for track in BGM_TRACKS {
+        let res = match bgm_track_bytes(track) {
+            Some(bytes) => match Decoder::new(Cursor::new(bytes)) {
+                Ok(mut dec) => dec.next().map(|_| ()).ok_or_else(|| "no samples".to_string()),
+                Err(e) => Err(e.to_string()),
+            },
+            None => Err("not embedded".to_string()),
+        };
+        record(format!("bgm:{track}"), res);
+    }
+    out
+}
 
-    tauri::Builder::default()
-        .plugin(tauri_plugin_opener::init())
-        .manage(AudioTx(tx))
-        .invoke_handler(tauri::generate_handler![greet, append_log, log_path, play_sfx, bgm_play, bgm_stop, bgm_volume])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+// A fully decoded sound: interleaved f32 samples plus their format.
+#[derive(Debug, Clone)]
+struct AudioClip {
+    channels: u16,
+    sample_rate: u32,
+    samples: Vec<f32>,
+}
+
+// Echo on a one-shot SFX: copies of the sound every `delay_ms`, the first at `mix` of the
+// original's level and each later one `feedback` times quieter than the one before.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+struct Echo {
+    delay_ms: u32,
+    feedback: f32,
+    mix: f32,
+}
+
+// Feedback below 1.0 so the taps always die away; the tap cap bounds the tail length.
+const ECHO_MAX_FEEDBACK: f32 = 0.9;
+const ECHO_MAX_DELAY_MS: u32 = 1000;
+const ECHO_MAX_TAPS: usize = 8;
+
+// Fade-in/out on decoded sample SFX: long enough to remove the click of a clip that starts or
+// ends on a non-zero sample, too short to hear. Procedural sounds have their own envelope.
+const SFX_EDGE_FADE: Duration = Duration::from_millis(1);
+
+impl AudioClip {
+    // What the SFX path (pan, echo) works on: mono or stereo, whole frames. Decoding already
+    // turned any sample format (8/16/24-bit, float) into f32; surround files are downmixed to
+    // mono here, stereo is kept so panning can balance it.
+    fn into_sfx_layout(mut self) -> AudioClip {
+        let channels = self.channels.max(1) as usize;
+        self.samples.truncate(self.samples.len() / channels * channels);
+        if channels > 2 {
+            self.samples = self.samples.chunks_exact(channels).map(|f| f.iter().sum::<f32>() / channels as f32).collect();
+            self.channels = 1;
+        }
+        self.channels = self.channels.max(1);
+        self
+    }
+
+    // Linear ramps over the first and last `fade` of the clip (at most half of it each).
+    fn with_edge_fades(mut self, fade: Duration) -> AudioClip {
+        let channels = self.channels.max(1) as usize;
+        let frames = self.samples.len() / channels;
+        let n = ((fade.as_secs_f64() * self.sample_rate as f64) as usize).min(frames / 2);
+        for i in 0..n {
+            let g = i as f32 / n as f32;
+            let tail = frames - 1 - i;
+            for c in 0..channels {
+                self.samples[i * channels + c] *= g;
+                self.samples[tail * channels + c] *= g;
+            }
+        }
+        self
+    }
+
+    // Feedback comb on the decoded samples: each tap is the dry clip delayed by another
+    // `delay_ms`, summed into a buffer long enough for the last tap to ring out.
+    fn with_echo(&self, echo: Echo) -> AudioClip {
+        let feedback = if echo.feedback.is_finite() { echo.feedback.clamp(0.0, ECHO_MAX_FEEDBACK) } else { 0.0 };
+        let mix = if echo.mix.is_finite() { echo.mix.clamp(0.0, 1.0) } else { 0.0 };
+        let delay_ms = echo.delay_ms.clamp(1, ECHO_MAX_DELAY_MS) as usize;
+        let channels = self.channels as usize;
+        let delay = (delay_ms * self.sample_rate as usize / 1000).max(1) * channels;
+        if mix == 0.0 || self.samples.is_empty() {
+            return self.clone();
+        }
+
+        let mut out = self.samples.clone();
+        out.resize(self.samples.len() + delay * ECHO_MAX_TAPS, 0.0);
+        let mut gain = mix;
+        let mut taps = 0;
+        while taps < ECHO_MAX_TAPS && gain > 0.001 {
+            taps += 1;
+            let offset = delay * taps;
+            for (o, &s) in out[offset..].iter_mut().zip(&self.samples) {
+                *o += s * gain;
+            }
+            gain *= feedback;
+        }
+        out.truncate(self.samples.len() + delay * taps);
+        AudioClip { channels: self.channels, sample_rate: self.sample_rate, samples: out }
+    }
+
+    fn to_source(&self) -> SamplesBuffer<f32> {
+        SamplesBuffer::new(self.channels, self.sample_rate, self.samples.clone())
+    }
+
+    fn into_source(self) -> SamplesBuffer<f32> {
+        SamplesBuffer::new(self.channels, self.sample_rate, self.samples)
+    }
+
+    // Equal-power pan (-1.0 left .. 1.0 right). Mono clips are upmixed to stereo first.
+    // Gains are scaled by sqrt(2) so a centered sound stays at the same level as an un-panned one.
+    fn panned(&self, pan: f32) -> SamplesBuffer<f32> {
+        if pan == 0.0 || self.channels > 2 {
+            return self.to_source();
+        }
+        let theta = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+        let gl = theta.cos() * std::f32::consts::SQRT_2;
+        let gr = theta.sin() * std::f32::consts::SQRT_2;
+
+        let out: Vec<f32> = if self.channels == 1 {
+            self.samples.iter().flat_map(|&s| [s * gl, s * gr]).collect()
+        } else {
+            self.samples.chunks_exact(2).flat_map(|f| [f[0] * gl, f[1] * gr]).collect()
+        };
+        SamplesBuffer::new(2, self.sample_rate, out)
+    }
+}
+
+// Length of the blend at each loop wrap; enough to hide a start/end mismatch without
+// audibly smearing the beat.
+const BGM_LOOP_CROSSFADE: Duration = Duration::from_millis(8);
+
+// Plays a decoded track once up to `loop_end`, then repeats [loop_start, loop_end) forever,
+// so an intro can lead into a seamless loop. Positions are sample indices on frame boundaries.
+// The last BGM_LOOP_CROSSFADE before `loop_end` is blended into the first one after
+// `loop_start`, and the wrap then resumes just past that head, so a loop whose ends don't
+// match doesn't click. That's one extra read and multiply per sample, and only inside the blend.
+struct LoopedTrack {
+    // Shared with the engine's track cache, so rebuilding a sink doesn't decode or copy it.
+    clip: Arc<AudioClip>,
+    pos: usize,
+    loop_start: usize,
+    loop_end: usize,
+    // Blend length in samples (whole frames; at most half the loop).
+    xfade: usize,
+}
+
+impl LoopedTrack {
+    // None when the points don't fit the track (start >= end or end past the last frame).
+    fn new(clip: Arc<AudioClip>, loop_start_ms: u64, loop_end_ms: u64) -> Option<Self> {
+        let frame = |ms: u64| (ms * clip.sample_rate as u64 / 1000) as usize * clip.channels as usize;
+        let (loop_start, loop_end) = (frame(loop_start_ms), frame(loop_end_ms));
+        Self::between(clip, loop_start, loop_end)
+    }
+
+    // Loops the whole clip.
+    fn whole(clip: Arc<AudioClip>) -> Option<Self> {
+        let channels = clip.channels as usize;
+        let end = clip.samples.len() - clip.samples.len() % channels.max(1);
+        Self::between(clip, 0, end)
+    }
+
+    fn between(clip: Arc<AudioClip>, loop_start: usize, loop_end: usize) -> Option<Self> {
+        if loop_start >= loop_end || loop_end > clip.samples.len() {
+            return None;
+        }
+        let channels = clip.channels as usize;
+        let frames = (BGM_LOOP_CROSSFADE.as_secs_f64() * clip.sample_rate as f64) as usize;
+        let xfade = (frames * channels).min((loop_end - loop_start) / 2 / channels * channels);
+        Some(Self { clip, pos: 0, loop_start, loop_end, xfade })
+    }
+}
+
+impl Iterator for LoopedTrack {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.loop_end {
+            // The head up to loop_start + xfade was already heard inside the blend.
+            self.pos = self.loop_start + self.xfade;
+        }
+        let mut s = self.clip.samples[self.pos];
+        let blend_start = self.loop_end - self.xfade;
+        if self.pos >= blend_start {
+            let k = self.pos - blend_start;
+            let t = k as f32 / self.xfade as f32;
+            s = s * (1.0 - t) + self.clip.samples[self.loop_start + k] * t;
+        }
+        self.pos += 1;
+        Some(s)
+    }
+}
+
+impl Source for LoopedTrack {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.clip.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.clip.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    // Positions past the loop end land where the loop would be after that much play time.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let channels = self.clip.channels as usize;
+        let mut idx = (pos.as_secs_f64() * self.clip.sample_rate as f64) as usize * channels;
+        if idx >= self.loop_end {
+            let head = self.loop_start + self.xfade;
+            idx = head + (idx - self.loop_end) % (self.loop_end - head);
+        }
+        self.pos = idx - idx % channels;
+        Ok(())
+    }
+}
+
+// Loops a user BGM file by re-opening and re-decoding it at each wrap. Memory stays at one
+// decoder's read buffer, whereas loading the bytes (or `repeat_infinite()`, which buffers every
+// decoded sample so it can replay them) would hold the whole track in RAM; a long track costs
+// a few MB compressed and tens of MB as PCM. The tradeoff is a file open per loop.
+struct FileLoop {
+    path: PathBuf,
+    dec: Decoder<BufReader<File>>,
+    events: AudioEvents,
+}
+
+impl FileLoop {
+    fn open(path: PathBuf, events: AudioEvents) -> Result<Self, String> {
+        let dec = open_decoder(&path)?;
+        Ok(Self { path, dec, events })
+    }
+}
+
+// Formats rodio is built to decode (see the rodio features in Cargo.toml).
+const SUPPORTED_AUDIO_FORMATS: &str = "WAV, OGG Vorbis, MP3 and FLAC";
+
+// Decoder failure on a user file, calling out files that aren't in a supported format at all
+// (the decoder probes every format, so that's the "unrecognized" case).
+fn decode_error_message(path: &std::path::Path, e: rodio::decoder::DecoderError) -> String {
+    match e {
+        rodio::decoder::DecoderError::UnrecognizedFormat => {
+            format!("unsupported audio format: {} (supported: {SUPPORTED_AUDIO_FORMATS})", path.display())
+        }
+        e => format!("decode {}: {e}", path.display()),
+    }
+}
+
+fn open_decoder(path: &std::path::Path) -> Result<Decoder<BufReader<File>>, String> {
+    let f = File::open(path).map_err(|e| format!("open {}: {e}", path.display()))?;
+    Decoder::new(BufReader::new(f)).map_err(|e| decode_error_message(path, e))
+}
+
+impl Iterator for FileLoop {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if let Some(s) = self.dec.next() {
+            return Some(s);
+        }
+        // End of file: start over. If the file vanished or decodes to nothing, end the source.
+        match open_decoder(&self.path) {
+            Ok(dec) => self.dec = dec,
+            Err(e) => {
+                DECODE_FAILURES.fetch_add(1, Ordering::Relaxed);
+                self.events.error("bgm file loop", e);
+                return None;
+            }
+        }
+        self.dec.next()
+    }
+}
+
+impl Source for FileLoop {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.dec.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.dec.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.dec.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.dec.try_seek(pos)
+    }
+}
+
+// Decode any seekable audio (embedded bytes or a user file) fully into memory.
+fn decode_reader<R>(data: R) -> Result<AudioClip, rodio::decoder::DecoderError>
+where
+    R: Read + Seek + Send + Sync + 'static,
+{
+    let src = Decoder::new(data)?;
+    let channels = src.channels();
+    let sample_rate = src.sample_rate();
+    let samples: Vec<f32> = src.convert_samples().collect();
+    Ok(AudioClip { channels, sample_rate, samples })
+}
+
+// Decode an embedded asset fully into memory so it can be cloned and replayed
+// without re-parsing the file on every shot.
+fn decode_buffer(bytes: &'static [u8]) -> Result<AudioClip, rodio::decoder::DecoderError> {
+    decode_reader(Cursor::new(bytes))
+}
+
+// Distance falloff for rival sounds: full volume within ENEMY_REF_DISTANCE, then inverse-square,
+// never below ENEMY_MIN_GAIN so far-off pickups stay faintly audible. Past ENEMY_MAX_DISTANCE
+// everything sits at the floor.
+const ENEMY_REF_DISTANCE: f32 = 4.0;
+const ENEMY_MAX_DISTANCE: f32 = 40.0;
+const ENEMY_MIN_GAIN: f32 = 0.12;
+
+fn distance_gain(distance: f32) -> f32 {
+    // NaN (bad input) plays at full volume, like the default distance of 0.
+    if distance.is_nan() || distance <= ENEMY_REF_DISTANCE {
+        return 1.0;
+    }
+    if distance >= ENEMY_MAX_DISTANCE {
+        return ENEMY_MIN_GAIN;
+    }
+    let g = ENEMY_REF_DISTANCE / distance;
+    (g * g).max(ENEMY_MIN_GAIN)
+}
+
+// Rival pickup chirp for a given variant. Variant 0 is the original sound; any other value
+// derives small, repeatable tweaks to the sweep, length and grit from the variant number.
+fn enemy_pickup_variant(variant: u32, sr: u32) -> AudioClip {
+    if variant == 0 {
+        return enemy_pickup_source(820.0, 260.0, 0.14, 0, sr);
+    }
+    let mut rng = Rng::seeded(variant as u64);
+    let f0 = 820.0 * rng.range(0.9, 1.1);
+    let f1 = 260.0 * rng.range(0.85, 1.15);
+    let dur_s = 0.14 * rng.range(0.9, 1.1);
+    enemy_pickup_source(f0, f1, dur_s, rng.next_u64(), sr)
+}
+
+fn enemy_pickup_source(f0: f32, f1: f32, dur_s: f32, seed: u64, sr: u32) -> AudioClip {
+    use synth::{Layer, SynthSpec, Wave};
+    // Procedural rival pickup sound: cyber "chirp" with a slight "digital" edge + sub click.
+    // Mono at `sr` (the output rate, so rodio needn't resample). `seed` != 0 mixes in a faint
+    // noise grain; 0 keeps it clean.
+    let mut layers = vec![
+        Layer { wave: Wave::Chirp { from: f0, to: f1 }, gain: 0.75, drive: Some(1.35), decay_ms: None },
+        Layer { wave: Wave::Sine { freq: 72.0 }, gain: 0.45, drive: None, decay_ms: Some(30.0) },
+    ];
+    if seed != 0 {
+        layers.push(Layer { wave: Wave::Noise { seed }, gain: 0.04, drive: None, decay_ms: None });
+    }
+    SynthSpec { duration_ms: dur_s * 1000.0, attack_ms: 10.0, curve: 2.0, layers }.render(sr)
+}
+
+// Procedural engine hum for the boost loop: a detuned saw-ish drone with a slow wobble.
+// Mono at `sr`, 0.5s long; every component completes a whole number of cycles in that time
+// so the buffer loops without a seam.
+fn boost_loop_clip(sr: u32) -> AudioClip {
+    let n = sr as usize / 2;
+    let tau = 2.0 * std::f32::consts::PI;
+    let mut out = Vec::with_capacity(n);
+
+    for i in 0..n {
+        let t = i as f32 / sr as f32;
+
+        // 110Hz fundamental + a couple of harmonics, and a slightly detuned second voice
+        let body = (tau * 110.0 * t).sin() + (tau * 220.0 * t).sin() * 0.5 + (tau * 330.0 * t).sin() * 0.25;
+        let detune = (tau * 112.0 * t).sin() * 0.4;
+
+        // 8Hz "rumble" wobble
+        let wobble = 0.85 + 0.15 * (tau * 8.0 * t).sin();
+
+        out.push(((body + detune) * 0.6).tanh() * wobble * 0.5);
+    }
+
+    AudioClip { channels: 1, sample_rate: sr, samples: out }
+}
+
+// Boost loop mapping: `speed` 0..1 sweeps playback rate and volume between these.
+const BOOST_PITCH_MIN: f32 = 0.7;
+const BOOST_PITCH_MAX: f32 = 1.6;
+const BOOST_VOL_MIN: f32 = 0.15;
+const BOOST_VOL_MAX: f32 = 0.5;
+// Fade-out on stop, long enough to avoid a click.
+const BOOST_RELEASE: Duration = Duration::from_millis(150);
+
+// Diagnostics: decode every embedded asset and report which are usable.
+#[tauri::command]
+fn verify_audio() -> Vec<(String, bool)> {
+    verify_audio_assets()
+}
+
+#[tauri::command]
+fn greet(name: &str) -> String {
+    format!("Hello, {}! You've been greeted from Rust!", name)
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct AppInfo {
+    name: String,
+    version: String,
+    // e.g. "windows-x86_64".
+    platform: String,
+    // The audio thread came up and answers queries.
+    audio_ok: bool,
+}
+
+// Startup health check and the build string for the About section.
+#[tauri::command]
+fn app_info(app: tauri::AppHandle, audio: tauri::State<'_, AudioTx>) -> AppInfo {
+    let package = app.package_info();
+    AppInfo {
+        name: package.name.clone(),
+        version: package.version.to_string(),
+        platform: format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH),
+        audio_ok: query_audio(&audio, |reply| AudioMsg::QueryBgm { reply }).is_some(),
+    }
+}
+
+const LOG_FILE_NAME: &str = "ultimate-snake.log";
+// Structured (JSONL) log, kept apart from the plain-text one so existing tooling isn't broken.
+const JSON_LOG_FILE_NAME: &str = "ultimate-snake.jsonl";
+// Once a log passes this size it's rotated to `<name>.1.gz` and a fresh one started.
+const LOG_MAX_BYTES: u64 = 5 * 1024 * 1024;
+// LogMode::PerSession: "ultimate-snake-<unix secs>.log", newest LOG_SESSIONS_KEPT kept.
+const SESSION_LOG_PREFIX: &str = "ultimate-snake-";
+const LOG_SESSIONS_KEPT: usize = 10;
+
+// Where the text log goes: one `ultimate-snake.log` (rotated by size), or a new file per
+// launch. Read from settings at startup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum LogMode {
+    #[default]
+    Single,
+    PerSession,
+}
+
+// Random per-launch id, stamped on structured log entries so lines from one run can be
+// grouped.
+fn current_session_id() -> &'static str {
+    static ID: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    ID.get_or_init(|| format!("{:016x}", Rng::from_time().next_u64()))
+}
+
+// `name` inside the app data dir, creating the dir if needed. Everything the game persists
+// lives there.
+fn app_data_file(app: &tauri::AppHandle, name: &str) -> Result<PathBuf, AppError> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| AppError::PathResolve(format!("app_data_dir: {e}")))?;
+
+    create_dir_all(&dir).map_err(|e| AppError::Io(format!("create_dir_all: {e}")))?;
+    Ok(dir.join(name))
+}
+
+// This launch's log (see `open_session_log`; moves to the temp dir if the first one can't be
+// written), or the single log before setup has picked one.
+fn log_file_path(app: &tauri::AppHandle) -> Result<PathBuf, AppError> {
+    match active_log_path() {
+        Some(path) => Ok(path),
+        None => app_data_file(app, LOG_FILE_NAME),
+    }
+}
+
+// Where a log goes when its own directory can't be written: same name, in the temp dir.
+fn temp_log_path(path: &std::path::Path) -> PathBuf {
+    std::env::temp_dir().join(path.file_name().unwrap_or_default())
+}
+
+// Pick the log file for this launch and remember it. Per-session mode prunes old session logs
+// first, leaving room for the new one. If the app data dir is unusable the log goes to the
+// temp dir instead, and the warning to write first is returned with it.
+fn open_session_log(app: &tauri::AppHandle, mode: LogMode) -> (PathBuf, Option<String>) {
+    let name = match mode {
+        LogMode::Single => LOG_FILE_NAME.to_string(),
+        LogMode::PerSession => {
+            let secs = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            format!("{SESSION_LOG_PREFIX}{secs}.log")
+        }
+    };
+    let (path, warning) = match app_data_file(app, &name) {
+        Ok(path) => (path, None),
+        Err(e) => {
+            let path = std::env::temp_dir().join(&name);
+            let warning = format!("log dir unavailable ({e}); logging to {} instead", path.display());
+            eprintln!("log warning: {warning}");
+            (path, Some(warning))
+        }
+    };
+    if mode == LogMode::PerSession {
+        if let Some(dir) = path.parent() {
+            prune_session_logs(dir, LOG_SESSIONS_KEPT - 1);
+        }
+    }
+    set_active_log_path(path.clone());
+    (path, warning)
+}
+
+// "ultimate-snake-<digits>.log"; the single log and the JSONL log don't match.
+fn is_session_log(name: &str) -> bool {
+    name.strip_prefix(SESSION_LOG_PREFIX)
+        .and_then(|rest| rest.strip_suffix(".log"))
+        .is_some_and(|stamp| !stamp.is_empty() && stamp.bytes().all(|b| b.is_ascii_digit()))
+}
+
+// Delete all but the newest `keep` session logs in `dir`, with their rotated files.
+fn prune_session_logs(dir: &std::path::Path, keep: usize) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    let mut logs: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(is_session_log))
+        .collect();
+    // Shorter stamps are older; equal lengths sort by value.
+    logs.sort_by_key(|p| (p.as_os_str().len(), p.clone()));
+    let excess = logs.len().saturating_sub(keep);
+    for path in &logs[..excess] {
+        for p in [path.clone(), rotated_log_path(path), compressed_log_path(path)] {
+            let _ = std::fs::remove_file(p);
+        }
+    }
+}
+
+// Write `value` as JSON to a temp file and rename it over `path`, so a crash mid-write
+// never leaves a half-written file behind.
+fn write_json_atomic<T: serde::Serialize>(path: &std::path::Path, value: &T) -> Result<(), AppError> {
+    let json = serde_json::to_vec_pretty(value).map_err(|e| AppError::Internal(format!("serialize {}: {e}", path.display())))?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json).map_err(|e| AppError::Io(format!("write {}: {e}", tmp.display())))?;
+    std::fs::rename(&tmp, path).map_err(|e| AppError::Io(format!("rename {}: {e}", path.display())))
+}
+
+// Read JSON from `path`; None if it's missing or unreadable/corrupt (reported on stderr),
+// so callers can fall back to a fresh value.
+fn read_json<T: serde::de::DeserializeOwned>(path: &std::path::Path) -> Option<T> {
+    let bytes = std::fs::read(path).ok()?;
+    match serde_json::from_slice(&bytes) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            eprintln!("{}: {e}; starting fresh", path.display());
+            None
+        }
+    }
+}
+
+const SETTINGS_FILE_NAME: &str = "settings.json";
+// Held by everything that writes settings.json, so a read-modify-write (`set_sfx_cooldown`)
+// can't interleave with a full `save_settings`.
+static SETTINGS_WRITE: Mutex<()> = Mutex::new(());
+
+// Saved player preferences. Missing fields (e.g. from an older file) take their defaults.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct Settings {
+    master_volume: f32,
+    sfx_volume: f32,
+    // BGM bus gain. The music (track) volume itself is kept separately, see `MusicVolume`.
+    bgm_volume: f32,
+    muted: bool,
+    // Output device name as listed by `list_audio_devices`; None = system default.
+    device: Option<String>,
+    // Pause BGM while the game window is unfocused.
+    pause_on_blur: bool,
+    // Single log file or one per launch; takes effect on the next launch.
+    log_mode: LogMode,
+    // Sum all output to mono (see `set_mono_output`).
+    mono_output: bool,
+    // Per-kind SFX cooldowns from `set_sfx_cooldown`, in ms.
+    sfx_cooldowns_ms: HashMap<String, u32>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self { master_volume: 1.0, sfx_volume: 1.0, bgm_volume: 1.0, muted: false, device: None, pause_on_blur: false, log_mode: LogMode::Single, mono_output: false, sfx_cooldowns_ms: HashMap::new() }
+    }
+}
+
+fn read_settings(app: &tauri::AppHandle) -> Settings {
+    match app_data_file(app, SETTINGS_FILE_NAME) {
+        Ok(path) => read_json(&path).unwrap_or_default(),
+        Err(_) => Settings::default(),
+    }
+}
+
+// Push saved settings into the mixer: bus volumes, mute and (if one was chosen) the device,
+// all in one message.
+fn apply_settings(tx: &Sender<AudioMsg>, settings: &Settings) {
+    let config = AudioConfig {
+        master_volume: settings.master_volume,
+        sfx_volume: settings.sfx_volume,
+        bgm_volume: settings.bgm_volume,
+        muted: settings.muted,
+        device: settings.device.clone(),
+    };
+    let _ = tx.send(AudioMsg::ApplyConfig { config });
+    MONO_OUTPUT.store(settings.mono_output, Ordering::Relaxed);
+    for (kind, &ms) in &settings.sfx_cooldowns_ms {
+        let _ = tx.send(AudioMsg::SfxCooldown { kind: kind.clone(), ms: ms.min(SFX_COOLDOWN_MAX_MS) as u64 });
+    }
+}
+
+const MUSIC_VOLUME_FILE_NAME: &str = "music_volume.json";
+// How long the music volume has to hold still before it's written, so a slider drag ends in
+// one write rather than one per step.
+const MUSIC_VOLUME_SAVE_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedMusicVolume {
+    volume: f32,
+}
+
+// The music (track) volume last passed to `bgm_play` / `bgm_volume`, remembered for the next
+// launch. It's the one place the startup music volume comes from (config.toml's
+// `default_music_volume` only applies until something has been saved), and it has its own
+// file because the frontend saves `Settings` whole. Commands only record the newest value;
+// `music_volume_thread` writes it once it settles and quitting writes whatever is left.
+struct MusicVolume {
+    path: Option<PathBuf>,
+    pending: Mutex<Option<f32>>,
+    // What's on disk. Held across the write, so writes land in the order they were taken.
+    saved: Mutex<Option<f32>>,
+    wake: Sender<()>,
+}
+
+impl MusicVolume {
+    fn new(path: Option<PathBuf>, wake: Sender<()>) -> Self {
+        let saved = path.as_deref().and_then(read_json::<SavedMusicVolume>).map(|s| s.volume);
+        Self { path, pending: Mutex::new(None), saved: Mutex::new(saved), wake }
+    }
+
+    fn saved(&self) -> Option<f32> {
+        self.saved.lock().ok().and_then(|s| *s)
+    }
+
+    fn set(&self, volume: f32) {
+        if let Ok(mut pending) = self.pending.lock() {
+            *pending = Some(volume);
+        }
+        let _ = self.wake.send(());
+    }
+
+    // A failed write is reported and dropped: the volume is already applied, it just won't
+    // survive this launch.
+    fn flush(&self) {
+        let Some(path) = &self.path else { return };
+        let Ok(mut saved) = self.saved.lock() else { return };
+        let Some(volume) = self.pending.lock().ok().and_then(|mut p| p.take()) else { return };
+        if *saved == Some(volume) {
+            return;
+        }
+        match write_json_atomic(path, &SavedMusicVolume { volume }) {
+            Ok(()) => *saved = Some(volume),
+            Err(e) => eprintln!("music volume: {e}"),
+        }
+    }
+}
+
+fn music_volume_thread(music: Arc<MusicVolume>, wake: Receiver<()>) {
+    while wake.recv().is_ok() {
+        while wake.recv_timeout(MUSIC_VOLUME_SAVE_DELAY).is_ok() {}
+        music.flush();
+    }
+}
+
+// Persist settings; they're applied to the mixer on the next launch. Live changes still go
+// through `set_bus_volume` / `set_muted` / `set_audio_device`.
+#[tauri::command]
+fn save_settings(app: tauri::AppHandle, settings: Settings) -> Result<(), AppError> {
+    let path = app_data_file(&app, SETTINGS_FILE_NAME)?;
+    let _guard = SETTINGS_WRITE.lock()?;
+    write_json_atomic(&path, &settings)
+}
+
+// Saved settings, or defaults when there's no (readable) file yet.
+#[tauri::command]
+fn load_settings(app: tauri::AppHandle) -> Settings {
+    read_settings(&app)
+}
+
+// Default and hard ceiling for `AudioTuning::sfx_max_gain`.
+const DEFAULT_SFX_MAX_GAIN: f32 = 2.0;
+const SFX_MAX_GAIN_LIMIT: f32 = 8.0;
+
+// Tester-tunable constants, read from config.toml in the app data dir at startup and on
+// `reload_config`. Missing file or fields fall back to the built-in values.
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct Config {
+    audio: AudioTuning,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct AudioTuning {
+    // Music volume until the player has picked one (see `MusicVolume`). Older files call it
+    // `bgm_volume`.
+    #[serde(alias = "bgm_volume")]
+    default_music_volume: f32,
+    // How far loud SFX duck the music, and how long it takes to come back.
+    duck_level: f32,
+    duck_release_ms: u64,
+    // Cooldown for kinds without their own entry, plus per-kind overrides
+    // (e.g. `[audio.sfx_cooldowns_ms]` / `eat = 50`).
+    sfx_cooldown_ms: u64,
+    sfx_cooldowns_ms: HashMap<String, u64>,
+    // Loudest an SFX may be driven (requested volume x trim), 1.0 = as mastered. Headroom
+    // above 1.0 lets quiet assets be pushed.
+    sfx_max_gain: f32,
+}
+
+impl Default for AudioTuning {
+    fn default() -> Self {
+        Self {
+            default_music_volume: 0.45,
+            duck_level: 0.3,
+            duck_release_ms: 400,
+            sfx_cooldown_ms: DEFAULT_SFX_COOLDOWN_MS,
+            sfx_cooldowns_ms: HashMap::new(),
+            sfx_max_gain: DEFAULT_SFX_MAX_GAIN,
+        }
+    }
+}
+
+impl AudioTuning {
+    // Out-of-range values from a hand-edited file are clamped rather than rejected.
+    fn sanitized(mut self) -> Self {
+        let defaults = Self::default();
+        self.default_music_volume = if self.default_music_volume.is_finite() {
+            self.default_music_volume.clamp(0.0, 1.0)
+        } else {
+            defaults.default_music_volume
+        };
+        self.duck_level = if self.duck_level.is_finite() { self.duck_level.clamp(0.0, 1.0) } else { defaults.duck_level };
+        self.sfx_max_gain = if self.sfx_max_gain.is_finite() {
+            self.sfx_max_gain.clamp(0.0, SFX_MAX_GAIN_LIMIT)
+        } else {
+            defaults.sfx_max_gain
+        };
+        self
+    }
+}
+
+// Ok(None) when there's no config file.
+fn read_config(app: &tauri::AppHandle) -> Result<Option<Config>, AppError> {
+    let path = app_data_file(app, CONFIG_FILE_NAME)?;
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(AppError::Io(format!("read {}: {e}", path.display()))),
+    };
+    toml::from_str(&text).map(Some).map_err(|e| AppError::Decode(format!("parse {}: {e}", path.display())))
+}
+
+// Only the tuning: the music volume is set once at startup (see `startup_music_volume`), so a
+// reload leaves the player's current music volume alone.
+fn apply_config(tx: &Sender<AudioMsg>, config: &Config) {
+    let tuning = config.audio.clone().sanitized();
+    let _ = tx.send(AudioMsg::Tuning { tuning });
+}
+
+// The saved music volume, or config.toml's default when nothing has been saved yet.
+fn startup_music_volume(saved: Option<f32>, config: &Config) -> f32 {
+    match saved {
+        Some(volume) => sanitize_gain(volume).min(1.0),
+        None => config.audio.clone().sanitized().default_music_volume,
+    }
+}
+
+// Re-read config.toml and push it to the audio thread. A broken file is reported and the
+// current values are kept; a missing one resets to the defaults. The music volume playing now
+// is kept either way.
+#[tauri::command]
+fn reload_config(app: tauri::AppHandle, state: tauri::State<'_, AudioTx>) -> Result<Config, AppError> {
+    let config = read_config(&app)?.unwrap_or_default();
+    apply_config(&state.0, &config);
+    Ok(config)
+}
+
+// Replays live in their own folder in the app data dir, one JSON file per saved run.
+const REPLAY_DIR_NAME: &str = "replays";
+const REPLAY_FORMAT_VERSION: u32 = 1;
+// Cap on recorded inputs (far more than a long run needs), so a stuck recorder can't grow
+// without bound.
+const REPLAY_MAX_EVENTS: usize = 200_000;
+
+// One player input. Serialized as e.g. "left", "dash" or {"choice": 2}.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Input {
+    Up,
+    Down,
+    Left,
+    Right,
+    Dash,
+    Pause,
+    Confirm,
+    // Picking an option on an upgrade/perk screen.
+    Choice(u32),
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct ReplayEvent {
+    frame: u64,
+    input: Input,
+}
+
+// A saved run: the RNG seed plus every input in frame order is enough to re-simulate it.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Replay {
+    version: u32,
+    seed: u64,
+    created: String,
+    events: Vec<ReplayEvent>,
+}
+
+// The run being recorded, if any.
+#[derive(Default)]
+struct ReplayState(Mutex<Option<Replay>>);
+
+fn replay_path(app: &tauri::AppHandle, name: &str) -> Result<PathBuf, AppError> {
+    // Names become file names, so keep them to a safe character set.
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(AppError::InvalidInput(format!("invalid replay name {name:?}: use letters, digits, '-' and '_'")));
+    }
+    let dir = app_data_file(app, REPLAY_DIR_NAME)?;
+    create_dir_all(&dir).map_err(|e| AppError::Io(format!("create_dir_all: {e}")))?;
+    Ok(dir.join(format!("{name}.json")))
+}
+
+// Start recording a new run (dropping any unsaved one), with the seed the game's RNG uses.
+#[tauri::command]
+fn replay_start(replay: tauri::State<'_, ReplayState>, seed: u64) -> Result<(), AppError> {
+    let mut rec = replay.0.lock()?;
+    *rec = Some(Replay { version: REPLAY_FORMAT_VERSION, seed, created: iso8601_now(), events: Vec::new() });
+    Ok(())
+}
+
+#[tauri::command]
+fn replay_record(replay: tauri::State<'_, ReplayState>, frame: u64, input: Input) -> Result<(), AppError> {
+    let mut rec = replay.0.lock()?;
+    let rec = rec.as_mut().ok_or_else(|| AppError::InvalidInput("no replay is being recorded".to_string()))?;
+    if rec.events.len() >= REPLAY_MAX_EVENTS {
+        return Err(AppError::InvalidInput(format!("replay is full ({REPLAY_MAX_EVENTS} inputs)")));
+    }
+    rec.events.push(ReplayEvent { frame, input });
+    Ok(())
+}
+
+// Write the recorded run to replays/<name>.json (replacing one of the same name) and end
+// the recording.
+#[tauri::command]
+fn replay_save(app: tauri::AppHandle, replay: tauri::State<'_, ReplayState>, name: String) -> Result<(), AppError> {
+    let path = replay_path(&app, &name)?;
+    let mut rec = replay.0.lock()?;
+    let run = rec.as_ref().ok_or_else(|| AppError::InvalidInput("no replay is being recorded".to_string()))?;
+    write_json_atomic(&path, run)?;
+    *rec = None;
+    Ok(())
+}
+
+// Load a saved replay for the frontend to re-simulate. Files from another format version are
+// rejected up front rather than half-parsed.
+#[tauri::command]
+fn replay_load(app: tauri::AppHandle, name: String) -> Result<Replay, AppError> {
+    let path = replay_path(&app, &name)?;
+    let bytes = std::fs::read(&path).map_err(|e| AppError::Io(format!("read {}: {e}", path.display())))?;
+    let value: serde_json::Value = serde_json::from_slice(&bytes)
+        .map_err(|e| AppError::Decode(format!("parse {}: {e}", path.display())))?;
+    match value.get("version").and_then(|v| v.as_u64()) {
+        Some(v) if v == u64::from(REPLAY_FORMAT_VERSION) => {}
+        Some(v) => {
+            return Err(AppError::Decode(format!(
+                "replay {name:?} is format version {v}, this build reads version {REPLAY_FORMAT_VERSION}"
+            )))
+        }
+        None => return Err(AppError::Decode(format!("replay {name:?} has no format version"))),
+    }
+    serde_json::from_value(value).map_err(|e| AppError::Decode(format!("parse {}: {e}", path.display())))
+}
+
+// Names of the saved replays (without extension), sorted.
+#[tauri::command]
+fn replay_list(app: tauri::AppHandle) -> Result<Vec<String>, AppError> {
+    let dir = app_data_file(&app, REPLAY_DIR_NAME)?;
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(AppError::Io(format!("read_dir {}: {e}", dir.display()))),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+            path.file_stem()?.to_str().map(str::to_string)
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+// The webview has no capture API, so screenshots go through the OS window list: find our
+// own process's window (preferring the one titled like the main window) and grab it.
+#[cfg(feature = "screenshot")]
+fn find_own_window(title: Option<&str>) -> Result<xcap::Window, AppError> {
+    let pid = std::process::id();
+    let mut ours: Vec<xcap::Window> = xcap::Window::all()
+        .map_err(|e| AppError::Window(format!("window capture isn't supported here: {e}")))?
+        .into_iter()
+        .filter(|w| w.pid().ok() == Some(pid) && !w.is_minimized().unwrap_or(false))
+        .collect();
+    if let Some(title) = title {
+        if let Some(i) = ours.iter().position(|w| w.title().ok().as_deref() == Some(title)) {
+            return Ok(ours.swap_remove(i));
+        }
+    }
+    // Otherwise the biggest of our windows is the game.
+    ours.into_iter()
+        .max_by_key(|w| w.width().unwrap_or(0) * w.height().unwrap_or(0))
+        .ok_or_else(|| AppError::Window("no capturable game window (minimized, or capture isn't supported here)".to_string()))
+}
+
+// Save the game window as a PNG. `dest` is a file path, or an existing folder to drop a
+// timestamped file into. Returns the written path. Runs off the main thread, since the
+// capture and PNG encode take a while. Needs the `screenshot` feature.
+#[cfg(feature = "screenshot")]
+#[tauri::command(async)]
+fn capture_screenshot(app: tauri::AppHandle, dest: String) -> Result<String, AppError> {
+    let mut path = PathBuf::from(dest);
+    if path.is_dir() {
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        path.push(format!("ultimate-snake-{secs}.png"));
+    }
+    let title = app.get_webview_window("main").and_then(|w| w.title().ok());
+    let image = find_own_window(title.as_deref())?
+        .capture_image()
+        .map_err(|e| AppError::Window(format!("capture_image: {e}")))?;
+    image
+        .save_with_format(&path, image::ImageFormat::Png)
+        .map_err(|e| AppError::Io(format!("write {}: {e}", path.display())))?;
+    Ok(path.display().to_string())
+}
+
+#[cfg(not(feature = "screenshot"))]
+#[tauri::command]
+fn capture_screenshot(_dest: String) -> Result<String, AppError> {
+    Err(AppError::Unsupported("screenshots aren't available in this build (enable the `screenshot` feature)".to_string()))
+}
+
+fn main_window(app: &tauri::AppHandle) -> Result<tauri::WebviewWindow, AppError> {
+    app.get_webview_window("main").ok_or_else(|| AppError::Window("main window not found".to_string()))
+}
+
+#[tauri::command]
+fn set_fullscreen(app: tauri::AppHandle, on: bool) -> Result<(), AppError> {
+    main_window(&app)?.set_fullscreen(on).map_err(|e| AppError::Window(format!("set_fullscreen: {e}")))
+}
+
+#[tauri::command]
+fn set_always_on_top(app: tauri::AppHandle, on: bool) -> Result<(), AppError> {
+    main_window(&app)?.set_always_on_top(on).map_err(|e| AppError::Window(format!("set_always_on_top: {e}")))
+}
+
+const HIGH_SCORES_FILE_NAME: &str = "highscores.json";
+const HIGH_SCORES_MAX: usize = 10;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct ScoreEntry {
+    name: String,
+    score: u32,
+    // ISO-8601 UTC, when the score was saved.
+    timestamp: String,
+}
+
+fn read_high_scores(path: &std::path::Path) -> Vec<ScoreEntry> {
+    read_json(path).unwrap_or_default()
+}
+
+// Add a score to the leaderboard (top HIGH_SCORES_MAX, highest first). Scores that don't
+// make the cut are simply not kept.
+#[tauri::command]
+fn save_high_score(app: tauri::AppHandle, score: u32, name: String) -> Result<(), AppError> {
+    let path = app_data_file(&app, HIGH_SCORES_FILE_NAME)?;
+    let mut scores = read_high_scores(&path);
+    scores.push(ScoreEntry { name, score, timestamp: iso8601_now() });
+    // Stable sort: on a tie the older score stays ahead.
+    scores.sort_by_key(|e| std::cmp::Reverse(e.score));
+    scores.truncate(HIGH_SCORES_MAX);
+    write_json_atomic(&path, &scores)
+}
+
+// Leaderboard, highest first. A missing or corrupt file reads as empty.
+#[tauri::command]
+fn load_high_scores(app: tauri::AppHandle) -> Result<Vec<ScoreEntry>, AppError> {
+    let path = app_data_file(&app, HIGH_SCORES_FILE_NAME)?;
+    Ok(read_high_scores(&path))
+}
+
+const STATS_FILE_NAME: &str = "stats.json";
+
+// Lifetime totals for the stats screen.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct Stats {
+    games_played: u64,
+    total_score: u64,
+    pellets_eaten: u64,
+    deaths: u64,
+    longest_snake: u32,
+}
+
+// Something that happened in a game, e.g. {"type": "game_over", "score": 120, "length": 14}.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StatEvent {
+    GameStarted,
+    PelletEaten,
+    Death,
+    GameOver { score: u32, length: u32 },
+}
+
+impl Stats {
+    fn record(&mut self, event: &StatEvent) {
+        match *event {
+            StatEvent::GameStarted => self.games_played += 1,
+            StatEvent::PelletEaten => self.pellets_eaten += 1,
+            StatEvent::Death => self.deaths += 1,
+            StatEvent::GameOver { score, length } => {
+                self.total_score += u64::from(score);
+                self.longest_snake = self.longest_snake.max(length);
+            }
+        }
+    }
+}
+
+// Cached totals, loaded from disk on first use. The lock is held across the update and the
+// write so concurrent `record_stat` calls can't lose increments.
+#[derive(Default)]
+struct StatsState(Mutex<Option<Stats>>);
+
+fn with_stats<T>(
+    app: &tauri::AppHandle,
+    state: &StatsState,
+    f: impl FnOnce(&mut Stats, &std::path::Path) -> Result<T, AppError>,
+) -> Result<T, AppError> {
+    let path = app_data_file(app, STATS_FILE_NAME)?;
+    let mut cached = state.0.lock()?;
+    let stats = cached.get_or_insert_with(|| read_json(&path).unwrap_or_default());
+    f(stats, &path)
+}
+
+#[tauri::command]
+fn record_stat(app: tauri::AppHandle, stats: tauri::State<'_, StatsState>, event: StatEvent) -> Result<(), AppError> {
+    with_stats(&app, &stats, |stats, path| {
+        stats.record(&event);
+        write_json_atomic(path, stats)
+    })
+}
+
+// Lifetime totals; all zero before the first recorded game.
+#[tauri::command]
+fn load_stats(app: tauri::AppHandle, stats: tauri::State<'_, StatsState>) -> Result<Stats, AppError> {
+    with_stats(&app, &stats, |stats, _| Ok(stats.clone()))
+}
+
+fn rotated_log_path(path: &std::path::Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".1");
+    path.with_file_name(name)
+}
+
+fn compressed_log_path(path: &std::path::Path) -> PathBuf {
+    let mut name = rotated_log_path(path).into_os_string();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+// Move an oversized log aside, then gzip it. The move is a single rename, so a crash at
+// any point leaves the lines in the active log, in `<name>.1`, or in `<name>.1.gz`; the
+// compressed file is written under a temp name and only renamed into place once complete,
+// and `<name>.1` is kept if compressing fails.
+fn rotate_log_if_needed(path: &std::path::Path) -> Result<(), String> {
+    let size = match std::fs::metadata(path) {
+        Ok(m) => m.len(),
+        Err(_) => return Ok(()),
+    };
+    if size <= LOG_MAX_BYTES {
+        return Ok(());
+    }
+    let rotated = rotated_log_path(path);
+    std::fs::rename(path, &rotated).map_err(|e| format!("rotate log: {e}"))?;
+    compress_log(&rotated, &compressed_log_path(path))
+}
+
+fn compress_log(src: &std::path::Path, dest: &std::path::Path) -> Result<(), String> {
+    let tmp = dest.with_extension("gz.tmp");
+    let write = || -> std::io::Result<()> {
+        let mut input = BufReader::new(File::open(src)?);
+        let mut enc = flate2::write::GzEncoder::new(File::create(&tmp)?, flate2::Compression::default());
+        std::io::copy(&mut input, &mut enc)?;
+        enc.finish()?.sync_all()?;
+        std::fs::rename(&tmp, dest)?;
+        std::fs::remove_file(src)
+    };
+    write().map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        format!("compress log {}: {e}", src.display())
+    })
+}
+
+// Lines of the previous (rotated) log: the gzip, or a leftover uncompressed `<name>.1` from
+// a rotation that didn't get to compress. Empty if there's neither.
+fn read_rotated_log_lines(path: &std::path::Path) -> std::io::Result<Vec<String>> {
+    let mut text = String::new();
+    let plain = rotated_log_path(path);
+    if plain.is_file() {
+        File::open(&plain)?.read_to_string(&mut text)?;
+    } else {
+        match File::open(compressed_log_path(path)) {
+            Ok(f) => {
+                flate2::read::GzDecoder::new(f).read_to_string(&mut text)?;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(text.lines().map(|l| l.to_string()).collect())
+}
+
+enum LogMsg {
+    Lines(Vec<String>),
+    // One already-serialized JSONL entry.
+    Json(String),
+    // Write out everything buffered, then reply (so a read sees every line sent before it).
+    Flush { reply: Sender<()> },
+    Clear { reply: Sender<Result<(), String>> },
+    Shutdown,
+}
+
+#[derive(Clone)]
+struct LogTx(Sender<LogMsg>);
+
+// Join handle of the log writer, so the exit path can wait for its final flush.
+type LogThread = Arc<Mutex<Option<JoinHandle<()>>>>;
+
+// Buffered lines are written out at least this often, or sooner once the buffer fills.
+const LOG_FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+const LOG_BUFFER_BYTES: usize = 64 * 1024;
+
+fn open_append(path: &std::path::Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// The open log file, owned by the log thread. Opened on first write and kept open; closed
+// (after a flush) to rotate, and flushed again when dropped. If the file can't be opened or
+// written it moves to the temp dir once (see `temp_log_path`), leaving a warning line there.
+struct LogWriter {
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+    // Bytes on disk plus bytes buffered, to know when to rotate without a stat per write.
+    size: u64,
+    // Set once the writer has moved to the temp dir; the log thread checks it to update
+    // `log_path`.
+    fell_back: bool,
+    // Why it moved, written as the first line of the fallback file.
+    fallback_note: Option<String>,
+}
+
+impl LogWriter {
+    fn new(path: PathBuf) -> Self {
+        Self { path, file: None, size: 0, fell_back: false, fallback_note: None }
+    }
+
+    fn file(&mut self) -> Result<&mut BufWriter<File>, String> {
+        if self.file.is_none() {
+            let f = match open_append(&self.path) {
+                Ok(f) => f,
+                Err(e) if self.fall_back(&format!("open log: {e}")) => open_append(&self.path).map_err(|e| format!("open log: {e}"))?,
+                Err(e) => return Err(format!("open log: {e}")),
+            };
+            self.size = f.metadata().map(|m| m.len()).unwrap_or(0);
+            let mut w = BufWriter::with_capacity(LOG_BUFFER_BYTES, f);
+            if let Some(note) = self.fallback_note.take() {
+                let line = format_log_line(LogLevel::Warn, &note);
+                self.size += line.len() as u64 + 1;
+                writeln!(w, "{line}").map_err(|e| format!("write log: {e}"))?;
+            }
+            self.file = Some(w);
+        }
+        self.file.as_mut().ok_or_else(|| "open log: no file".to_string())
+    }
+
+    // Switch to the temp-dir copy of this log (only once, and not if already there).
+    fn fall_back(&mut self, reason: &str) -> bool {
+        let temp = temp_log_path(&self.path);
+        if self.fell_back || temp == self.path {
+            return false;
+        }
+        let note = format!("{reason} ({}); logging here instead", self.path.display());
+        eprintln!("log warning: {note}");
+        self.file = None;
+        self.path = temp;
+        self.fell_back = true;
+        self.fallback_note = Some(note);
+        true
+    }
+
+    fn write_lines(&mut self, lines: &[String]) -> Result<(), String> {
+        match self.append(lines) {
+            Err(e) if self.fall_back(&e) => self.append(lines)?,
+            res => res?,
+        }
+        if self.size > LOG_MAX_BYTES {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, lines: &[String]) -> Result<(), String> {
+        let added: u64 = lines.iter().map(|l| l.len() as u64 + 1).sum();
+        let f = self.file()?;
+        for line in lines {
+            writeln!(f, "{}", line).map_err(|e| format!("write log: {e}"))?;
+        }
+        self.size += added;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        match &mut self.file {
+            Some(f) => f.flush().map_err(|e| format!("flush log: {e}")),
+            None => Ok(()),
+        }
+    }
+
+    fn rotate(&mut self) -> Result<(), String> {
+        self.flush()?;
+        // Closed before renaming: Windows won't rename a file that's still open.
+        self.file = None;
+        self.size = 0;
+        rotate_log_if_needed(&self.path)
+    }
+
+    // Truncate the log. Lines still buffered are from before the clear, so they're discarded.
+    fn clear(&mut self) -> Result<(), String> {
+        if let Some(f) = self.file.take() {
+            let _ = f.into_parts();
+        }
+        self.size = 0;
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)
+            .map(|_| ())
+            .map_err(|e| format!("clear log {}: {e}", self.path.display()))
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("log error: {e}");
+        }
+    }
+}
+
+// Log writer thread: same shape as the audio thread. Blocks while idle; while lines are
+// buffered it wakes after LOG_FLUSH_INTERVAL to write them out. Exits (flushing) on
+// Shutdown or when every sender is gone.
+fn log_thread(rx: Receiver<LogMsg>, path: PathBuf) {
+    let mut json = LogWriter::new(path.with_file_name(JSON_LOG_FILE_NAME));
+    let mut writer = LogWriter::new(path);
+    let mut dirty = false;
+    let flush = |writer: &mut LogWriter, json: &mut LogWriter| {
+        for w in [writer, json] {
+            if let Err(e) = w.flush() {
+                eprintln!("log error: {e}");
+            }
+        }
+    };
+    loop {
+        let msg = if dirty {
+            match rx.recv_timeout(LOG_FLUSH_INTERVAL) {
+                Ok(m) => Some(m),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        } else {
+            match rx.recv() {
+                Ok(m) => Some(m),
+                Err(_) => break,
+            }
+        };
+
+        match msg {
+            None => {
+                flush(&mut writer, &mut json);
+                dirty = false;
+            }
+            Some(LogMsg::Lines(lines)) => {
+                if let Err(e) = writer.write_lines(&lines) {
+                    eprintln!("log error: {e}");
+                }
+                if writer.fell_back && active_log_path().as_ref() != Some(&writer.path) {
+                    set_active_log_path(writer.path.clone());
+                }
+                dirty = true;
+            }
+            Some(LogMsg::Json(line)) => {
+                if let Err(e) = json.write_lines(&[line]) {
+                    eprintln!("log error: {e}");
+                }
+                dirty = true;
+            }
+            Some(LogMsg::Flush { reply }) => {
+                flush(&mut writer, &mut json);
+                dirty = false;
+                let _ = reply.send(());
+            }
+            Some(LogMsg::Clear { reply }) => {
+                // Only the plain log is cleared; the JSONL log's buffered lines still go out.
+                if let Err(e) = json.flush() {
+                    eprintln!("log error: {e}");
+                }
+                let _ = reply.send(writer.clear());
+                dirty = false;
+            }
+            Some(LogMsg::Shutdown) => break,
+        }
+    }
+    // Both writers flush as they drop here.
+}
+
+// This launch's log file. Set in setup (and again if the writer falls back to the temp dir);
+// also used by the panic hook, which runs without an AppHandle.
+static ACTIVE_LOG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+fn active_log_path() -> Option<PathBuf> {
+    ACTIVE_LOG_PATH.lock().ok()?.clone()
+}
+
+fn set_active_log_path(path: PathBuf) {
+    if let Ok(mut p) = ACTIVE_LOG_PATH.lock() {
+        *p = Some(path);
+    }
+}
+
+// Record panics (message, location and backtrace) in the log, then run the default hook as
+// well. Written straight to the file rather than through the log thread, since the panic may
+// be on that thread or the process may be about to abort.
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // try_lock: the panic may have happened while the path was being updated.
+        if let Some(path) = ACTIVE_LOG_PATH.try_lock().ok().and_then(|p| p.clone()) {
+            let thread = std::thread::current();
+            let message = format!(
+                "panic in thread '{}': {info}\n{}",
+                thread.name().unwrap_or("<unnamed>"),
+                std::backtrace::Backtrace::force_capture()
+            );
+            if let Ok(mut f) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(f, "{}", format_log_line(LogLevel::Error, &message));
+            }
+        }
+        default_hook(info);
+    }));
+}
+
+// "=== session start|end <timestamp> <version> <session id> ===", bracketing each run in the log.
+fn session_marker(kind: &str, version: &str) -> String {
+    format!("=== session {kind} {} {version} {} ===", iso8601_now(), current_session_id())
+}
+
+// Queue raw lines for the log writer; returns without waiting for the disk.
+fn write_log_lines(log: &LogTx, lines: Vec<String>) -> Result<(), AppError> {
+    log.0.send(LogMsg::Lines(lines)).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn append_log(log: tauri::State<'_, LogTx>, lines: Vec<String>) -> Result<(), AppError> {
+    write_log_lines(&log, lines)
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Trace => "TRACE",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+}
+
+// Current UTC time as ISO-8601 with milliseconds, e.g. "2024-05-01T12:34:56.789Z".
+fn iso8601_now() -> String {
+    let since_epoch = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    let (h, m, s) = (rem / 3600, rem % 3600 / 60, rem % 60);
+
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's days_from_civil, inverted).
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{h:02}:{m:02}:{s:02}.{:03}Z",
+        since_epoch.subsec_millis()
+    )
+}
+
+// One log line: "<timestamp> [LEVEL] message", so the log can be grepped by severity.
+fn format_log_line(level: LogLevel, message: &str) -> String {
+    format!("{} [{}] {}", iso8601_now(), level.as_str(), message)
+}
+
+// This launch's id, as written in the session markers and structured log entries, so other
+// log lines can be correlated with it.
+#[tauri::command]
+fn session_id() -> String {
+    current_session_id().to_string()
+}
+
+// `level` is "trace", "debug", "info", "warn" or "error".
+#[tauri::command]
+fn log_event(log: tauri::State<'_, LogTx>, level: LogLevel, message: String) -> Result<(), AppError> {
+    write_log_lines(&log, vec![format_log_line(level, &message)])
+}
+
+// A value for a "key=value" field: quoted (with `"` and `\` escaped) when it is empty or holds
+// whitespace, `=` or a quote, so the line still splits unambiguously. Newlines are escaped too,
+// keeping each entry on one line.
+fn kv_escape(value: &str) -> String {
+    let needs_quotes = value.is_empty() || value.chars().any(|c| c.is_whitespace() || c == '=' || c == '"');
+    if !needs_quotes {
+        return value.to_string();
+    }
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// "<timestamp> key=value ...", keys sorted so the same fields always come out in the same order.
+// Keys go through the same escaping as values.
+fn format_kv_line(fields: &HashMap<String, String>) -> String {
+    let mut keys: Vec<&String> = fields.keys().collect();
+    keys.sort();
+    let mut line = iso8601_now();
+    for key in keys {
+        line.push(' ');
+        line.push_str(&kv_escape(key));
+        line.push('=');
+        line.push_str(&kv_escape(&fields[key]));
+    }
+    line
+}
+
+// Semi-structured entry in the plain log: one line of "key=value" pairs, still readable without
+// JSON tooling (see `log_json` for that).
+#[tauri::command]
+fn log_kv(log: tauri::State<'_, LogTx>, fields: HashMap<String, String>) -> Result<(), AppError> {
+    write_log_lines(&log, vec![format_kv_line(&fields)])
+}
+
+// `pitch` is a playback-speed ratio (1.0 = unchanged). If `pitch_range` ([min, max]) is given,
+// a random ratio from that range is picked per shot instead, so repeats don't sound identical.
+// `pan` places the sound left (-1.0) to right (1.0); omitted means centered.
+// `variant` picks a repeatable variation of procedural sounds ("enemy_pickup"); 0 is the default.
+// `echo` ({ delay_ms, feedback, mix }) adds decaying repeats; omitted means dry.
+// `sample` forces which of a kind's samples plays (wrapped to the count); omitted = random.
+// `distance` (game units) from the player to a rival makes "enemy_pickup" quieter; 0 / omitted
+// is full volume.
+// `volume` is capped on the audio thread at the configured `sfx_max_gain` (default 2.0).
+// Returns an id for `stop_sfx` (0 when nothing was played, e.g. muted or the SFX queue was
+// full).
+// "ui" goes over the control channel instead of the SFX queue, so menu clicks are never
+// dropped under load; on the audio thread it has its own sinks with no cooldown or ducking.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn play_sfx(
+    state: tauri::State<'_, AudioTx>,
+    sfx: tauri::State<'_, SfxTx>,
+    kind: String,
+    volume: f32,
+    muted: bool,
+    pitch: Option<f32>,
+    pitch_range: Option<[f32; 2]>,
+    pan: Option<f32>,
+    variant: Option<u32>,
+    echo: Option<Echo>,
+    sample: Option<u32>,
+    distance: Option<f32>,
+) -> Result<u64, AppError> {
+    let volume = sanitize_gain(volume);
+    if muted || volume <= 0.0001 {
+        return Ok(0);
+    }
+    let id = NEXT_SFX_ID.fetch_add(1, Ordering::Relaxed);
+    let ui = kind == "ui";
+    let msg = AudioMsg::Sfx {
+        id,
+        kind,
+        volume,
+        pitch: pitch.unwrap_or(1.0),
+        pitch_range: pitch_range.map(|[lo, hi]| (lo, hi)),
+        pan: pan.unwrap_or(0.0),
+        variant: variant.unwrap_or(0),
+        echo,
+        sample,
+        distance: distance.unwrap_or(0.0),
+    };
+    // send to audio thread (which owns OutputStream)
+    if ui {
+        state.send(msg)?;
+        return Ok(id);
+    }
+    let sent = sfx.send(msg, 1)?;
+    Ok(if sent { id } else { 0 })
+}
+
+// Play a sound described by `spec` (sine / saw / chirp / noise layers under one envelope, see
+// `synth::SynthSpec`), so new SFX can be designed from the frontend without shipping a WAV.
+// Returns an id for `stop_sfx` like `play_sfx` (0 when nothing was played).
+#[tauri::command]
+fn play_synth(sfx: tauri::State<'_, SfxTx>, spec: synth::SynthSpec, volume: f32) -> Result<u64, AppError> {
+    spec.validate().map_err(AppError::InvalidInput)?;
+    let volume = sanitize_gain(volume);
+    if volume <= 0.0001 {
+        return Ok(0);
+    }
+    let id = NEXT_SFX_ID.fetch_add(1, Ordering::Relaxed);
+    let sent = sfx.send(AudioMsg::Synth { id, spec, volume }, 1)?;
+    Ok(if sent { id } else { 0 })
+}
+
+// Prime the output again (it already is once at startup), e.g. from the splash screen.
+// Silent.
+#[tauri::command]
+fn audio_warmup(state: tauri::State<'_, AudioTx>) -> Result<(), AppError> {
+    state.send(AudioMsg::Warmup)
+}
+
+// Minimum gap between two plays of `kind` (0 = none), e.g. a shorter "eat" cooldown for a fast
+// mode. Applies right away and is saved into the settings file when there is one, so it
+// sticks across launches.
+#[tauri::command]
+fn set_sfx_cooldown(app: tauri::AppHandle, state: tauri::State<'_, AudioTx>, kind: String, ms: u32) -> Result<(), AppError> {
+    if !list_sfx_kinds().contains(&kind) {
+        return Err(AppError::InvalidInput(format!("unknown sfx kind: {kind}")));
+    }
+    if ms > SFX_COOLDOWN_MAX_MS {
+        return Err(AppError::InvalidInput(format!("cooldown must be at most {SFX_COOLDOWN_MAX_MS}ms")));
+    }
+    state.send(AudioMsg::SfxCooldown { kind: kind.clone(), ms: ms as u64 })?;
+    let path = app_data_file(&app, SETTINGS_FILE_NAME)?;
+    let _guard = SETTINGS_WRITE.lock()?;
+    if path.is_file() {
+        let mut settings = read_settings(&app);
+        settings.sfx_cooldowns_ms.insert(kind, ms);
+        write_json_atomic(&path, &settings)?;
+    }
+    Ok(())
+}
+
+// Every kind `play_sfx` can play, for the sound test screen.
+#[tauri::command]
+fn list_sfx_kinds() -> Vec<String> {
+    SFX_KINDS
+        .iter()
+        .filter(|kind| !sfx_bytes(kind).is_empty())
+        .chain(std::iter::once(&"enemy_pickup"))
+        .map(|kind| kind.to_string())
+        .collect()
+}
+
+// Audition one SFX for the sound test: plays even while muted, with no cooldown or menu duck,
+// on its own sink so it never takes a gameplay voice. A new preview cuts off the previous one.
+#[tauri::command]
+fn preview_sfx(state: tauri::State<'_, AudioTx>, kind: String, volume: f32) -> Result<(), AppError> {
+    if kind != "enemy_pickup" && sfx_bytes(&kind).is_empty() {
+        return Err(AppError::InvalidInput(format!("unknown sfx kind: {kind}")));
+    }
+    let volume = sanitize_gain(volume);
+    state.send(AudioMsg::PreviewSfx { kind, volume })
+}
+
+// Limits for `play_test_tone`: the audible range, and long enough to find the volume knob.
+const TEST_TONE_FREQ_MIN: f32 = 20.0;
+const TEST_TONE_FREQ_MAX: f32 = 20_000.0;
+const TEST_TONE_MAX_MS: u32 = 3000;
+// Ramp on each end of the tone so it doesn't start or stop with a click.
+const TEST_TONE_FADE: Duration = Duration::from_millis(10);
+
+// Sine at `freq_hz` for checking that output works at all. Plays on the preview sink at
+// `volume` (0..1) alone: mute, buses and the menu duck don't apply.
+#[tauri::command]
+fn play_test_tone(state: tauri::State<'_, AudioTx>, freq_hz: f32, duration_ms: u32, volume: f32) -> Result<(), AppError> {
+    let freq = if freq_hz.is_finite() { freq_hz.clamp(TEST_TONE_FREQ_MIN, TEST_TONE_FREQ_MAX) } else { 440.0 };
+    let duration = Duration::from_millis(duration_ms.clamp(1, TEST_TONE_MAX_MS) as u64);
+    state.send(AudioMsg::TestTone { freq, duration, volume: sanitize_gain(volume).min(1.0) })
+}
+
+// One entry of `play_sfx_batch`; missing fields default like `play_sfx`'s.
+#[derive(Debug, Clone, serde::Deserialize)]
+struct SfxEvent {
+    kind: String,
+    volume: f32,
+    pan: Option<f32>,
+    pitch: Option<f32>,
+}
+
+// Several SFX in one invoke (e.g. a multi-pellet combo). They go to the audio thread as one
+// message and play as if sent one by one, so cooldowns and pool limits still apply
+// (two of the same kind in one batch plays only the first). Returns one id per event,
+// 0 for ones that were skipped as silent (or all 0 if the batch was dropped under load).
+#[tauri::command]
+fn play_sfx_batch(sfx: tauri::State<'_, SfxTx>, events: Vec<SfxEvent>) -> Result<Vec<u64>, AppError> {
+    let mut ids = Vec::with_capacity(events.len());
+    let mut shots = Vec::with_capacity(events.len());
+    for mut ev in events {
+        ev.volume = sanitize_gain(ev.volume);
+        if ev.volume <= 0.0001 {
+            ids.push(0);
+            continue;
+        }
+        let id = NEXT_SFX_ID.fetch_add(1, Ordering::Relaxed);
+        ids.push(id);
+        shots.push((id, ev));
+    }
+    if !shots.is_empty() {
+        let count = shots.len() as u64;
+        if !sfx.send(AudioMsg::SfxBatch { shots }, count)? {
+            ids.iter_mut().for_each(|id| *id = 0);
+        }
+    }
+    Ok(ids)
+}
+
+// Stop one sound started by `play_sfx`. Unknown or already-finished ids are ignored.
+#[tauri::command]
+fn stop_sfx(sfx: tauri::State<'_, SfxTx>, id: u64) -> Result<(), AppError> {
+    sfx.send_ordered(AudioMsg::SfxStop { id })
+}
+
+// Cut every playing SFX (including a boost loop and a settings preview), e.g. on a scene
+// change so a long "death" doesn't bleed into the next screen. BGM is left alone.
+#[tauri::command]
+fn stop_all_sfx(sfx: tauri::State<'_, SfxTx>) -> Result<(), AppError> {
+    sfx.send_ordered(AudioMsg::SfxStopAll)
+}
+
+// Most SFX that can be waiting in `schedule_sfx` at once; further ones are dropped (and
+// counted in SFX_DROPPED).
+const SCHEDULED_SFX_MAX: usize = 256;
+
+// Play `kind` `at_ms_from_now` ms from now, timed on the audio thread rather than with a
+// frontend timer, so e.g. a countdown beep every second doesn't drift. The thread sleeps until
+// the earliest pending shot, so one fires within a millisecond or two of its target (the
+// wake-up granularity of its timed recv), plus the output latency every SFX has. It goes through
+// the normal cooldown, mute and menu duck when it fires. Returns an id for `stop_sfx`, which
+// also cancels it if it hasn't fired yet.
+#[tauri::command]
+fn schedule_sfx(sfx: tauri::State<'_, SfxTx>, kind: String, at_ms_from_now: u64, volume: f32) -> Result<u64, AppError> {
+    if kind != "enemy_pickup" && sfx_bytes(&kind).is_empty() {
+        return Err(AppError::InvalidInput(format!("unknown sfx kind: {kind}")));
+    }
+    let volume = sanitize_gain(volume);
+    if volume <= 0.0001 {
+        return Ok(0);
+    }
+    let id = NEXT_SFX_ID.fetch_add(1, Ordering::Relaxed);
+    let at = Instant::now() + Duration::from_millis(at_ms_from_now);
+    sfx.send_ordered(AudioMsg::ScheduleSfx { id, kind, at, volume })?;
+    Ok(id)
+}
+
+// Drop every SFX still waiting from `schedule_sfx`. Ones that already fired keep playing.
+#[tauri::command]
+fn cancel_scheduled_sfx(sfx: tauri::State<'_, SfxTx>) -> Result<(), AppError> {
+    sfx.send_ordered(AudioMsg::CancelScheduledSfx)
+}
+
+
+// `track` names an embedded track (default "gameplay"); unknown names are an error.
+// `volume` is saved for the next launch; omitted keeps the current music volume (at startup,
+// the saved one).
+// `fade_ms` fades a newly started track in (0 / omitted = start at full volume).
+// Asking for the track that's already playing only updates the volume (so calling this on
+// every route change doesn't restart the music); a different track replaces it, crossfading
+// over `fade_ms` when one is given.
+// `loop_start_ms` + `loop_end_ms` play the intro once, then loop just that section. The end is
+// checked against the track length on the audio thread (out of range = whole-track loop).
+// `looping: false` plays the track once (e.g. a victory jingle) and emits "bgm-finished" with
+// the track name when it ends; omitted = loop forever.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn bgm_play(
+    state: tauri::State<'_, AudioTx>,
+    music: tauri::State<'_, Arc<MusicVolume>>,
+    volume: Option<f32>,
+    muted: bool,
+    fade_ms: Option<u32>,
+    track: Option<String>,
+    loop_start_ms: Option<u64>,
+    loop_end_ms: Option<u64>,
+    looping: Option<bool>,
+) -> Result<(), AppError> {
+    let looping = looping.unwrap_or(true);
+    let track = track.unwrap_or_else(|| DEFAULT_BGM_TRACK.to_string());
+    if bgm_track_bytes(&track).is_none() {
+        return Err(AppError::InvalidInput(format!("unknown bgm track: {track}")));
+    }
+    let loop_points = match (loop_start_ms, loop_end_ms) {
+        (None, None) => None,
+        (Some(start), Some(end)) if start < end => Some((start, end)),
+        (Some(start), Some(end)) => {
+            return Err(AppError::InvalidInput(format!("loop_start_ms ({start}) must be before loop_end_ms ({end})")))
+        }
+        _ => return Err(AppError::InvalidInput("loop_start_ms and loop_end_ms must be given together".to_string())),
+    };
+    if loop_points.is_some() && !looping {
+        return Err(AppError::InvalidInput("loop points need looping playback".to_string()));
+    }
+    let volume = volume.map(|v| sanitize_gain(v).min(1.0));
+    let played = if muted || volume.is_some_and(|v| v <= 0.0001) {
+        Ok(())
+    } else {
+        let fade = Duration::from_millis(fade_ms.unwrap_or(0) as u64);
+        state.send(AudioMsg::BgmPlay { track, volume, fade, loop_points, looping })
+    };
+    if let Some(v) = volume {
+        music.set(v);
+    }
+    played
+}
+
+// Fade the current BGM out while `track` fades in over `duration_ms`. Calling this again
+// mid-fade starts a new crossfade from wherever the volumes currently are.
+#[tauri::command]
+fn bgm_crossfade(state: tauri::State<'_, AudioTx>, track: String, volume: f32, duration_ms: u32) -> Result<(), AppError> {
+    if bgm_track_bytes(&track).is_none() {
+        return Err(AppError::InvalidInput(format!("unknown bgm track: {track}")));
+    }
+    state
+        .send(AudioMsg::BgmCrossfade {
+            track,
+            volume: sanitize_gain(volume).min(1.0),
+            duration: Duration::from_millis(duration_ms as u64),
+        })
+}
+
+const BEAT_SWITCH_BPM_MIN: f32 = 20.0;
+const BEAT_SWITCH_BPM_MAX: f32 = 400.0;
+// Crossfade length for `bgm_switch_on_beat` when no `fade_ms` is given.
+const BEAT_SWITCH_FADE: Duration = Duration::from_millis(250);
+
+// Crossfade to `track` starting on the next beat of the current music, going by its position
+// and `bpm` (beats counted from the start of the track). Starts straight away when nothing is
+// playing. Volume carries over from the current track.
+#[tauri::command]
+fn bgm_switch_on_beat(state: tauri::State<'_, AudioTx>, track: String, bpm: f32, fade_ms: Option<u32>) -> Result<(), AppError> {
+    if bgm_track_bytes(&track).is_none() {
+        return Err(AppError::InvalidInput(format!("unknown bgm track: {track}")));
+    }
+    if !(BEAT_SWITCH_BPM_MIN..=BEAT_SWITCH_BPM_MAX).contains(&bpm) {
+        return Err(AppError::InvalidInput(format!("bpm must be {BEAT_SWITCH_BPM_MIN}..{BEAT_SWITCH_BPM_MAX}, got {bpm}")));
+    }
+    let fade = fade_ms.map_or(BEAT_SWITCH_FADE, |ms| Duration::from_millis(ms as u64));
+    state.send(AudioMsg::BgmSwitchOnBeat { track, bpm, fade })
+}
+
+// Time from `pos` to the next beat at `bpm`.
+fn until_next_beat(pos: Duration, bpm: f32) -> Duration {
+    let beat = 60.0 / bpm as f64;
+    Duration::from_secs_f64(beat - pos.as_secs_f64() % beat)
+}
+
+// `fade_ms` fades the music out before stopping it (0 / omitted = cut immediately).
+#[tauri::command]
+fn bgm_stop(state: tauri::State<'_, AudioTx>, fade_ms: Option<u32>) -> Result<(), AppError> {
+    let fade = Duration::from_millis(fade_ms.unwrap_or(0) as u64);
+    state.send(AudioMsg::BgmStop { fade })
+}
+
+// Custom audio lives in the app data dir; relative paths are resolved against it.
+fn resolve_user_audio_path(app: &tauri::AppHandle, path: &str) -> Result<PathBuf, AppError> {
+    let mut p = PathBuf::from(path);
+    if p.is_relative() {
+        let dir = app
+            .path()
+            .app_data_dir()
+            .map_err(|e| AppError::PathResolve(format!("app_data_dir: {e}")))?;
+        p = dir.join(p);
+    }
+    if !p.is_file() {
+        return Err(AppError::PathResolve(format!("audio file not found: {}", p.display())));
+    }
+    Ok(p)
+}
+
+fn read_user_audio(app: &tauri::AppHandle, path: &str) -> Result<(PathBuf, Vec<u8>), AppError> {
+    let p = resolve_user_audio_path(app, path)?;
+    let bytes = std::fs::read(&p).map_err(|e| AppError::Io(format!("read {}: {e}", p.display())))?;
+    Ok((p, bytes))
+}
+
+// Play a user-supplied file as a one-shot SFX. Decoded here so a bad file is reported to the
+// caller instead of failing silently on the audio thread. Returns an id for `stop_sfx`.
+// Supported formats: WAV, OGG Vorbis, MP3 and FLAC; anything else is an "unsupported audio
+// format" error. Runs off the main thread, since the file can be any size. Muted or silent
+// calls return 0 without touching the file.
+#[tauri::command(async)]
+fn play_sfx_file(
+    app: tauri::AppHandle,
+    sfx: tauri::State<'_, SfxTx>,
+    path: String,
+    volume: f32,
+    muted: bool,
+) -> Result<u64, AppError> {
+    let volume = sanitize_gain(volume);
+    if muted || volume <= 0.0001 {
+        return Ok(0);
+    }
+    let (p, bytes) = read_user_audio(&app, &path)?;
+    let clip = decode_reader(Cursor::new(bytes))
+        .map_err(|e| {
+            DECODE_FAILURES.fetch_add(1, Ordering::Relaxed);
+            AppError::Decode(decode_error_message(&p, e))
+        })?
+        .into_sfx_layout()
+        .with_edge_fades(SFX_EDGE_FADE);
+    let id = NEXT_SFX_ID.fetch_add(1, Ordering::Relaxed);
+    let sent = sfx.send(AudioMsg::SfxFile { id, clip, volume }, 1)?;
+    Ok(if sent { id } else { 0 })
+}
+
+// `analyze_loudness` levels files to this RMS (about -20 dBFS, roughly where the bundled
+// tracks sit). Suggestions stay within the gain limits so near-silent files aren't blown up.
+const LOUDNESS_TARGET_RMS: f64 = 0.1;
+const LOUDNESS_GAIN_MIN: f32 = 0.1;
+const LOUDNESS_GAIN_MAX: f32 = 4.0;
+
+// Suggested volume multiplier that brings a user audio file to LOUDNESS_TARGET_RMS over its
+// whole length, lowered if needed so its peak doesn't clip. Silent files get 1.0. Only reads
+// the file. Runs off the main thread, since decoding a whole song takes a moment.
+#[tauri::command(async)]
+fn analyze_loudness(app: tauri::AppHandle, path: String) -> Result<f32, AppError> {
+    let p = resolve_user_audio_path(&app, &path)?;
+    let dec = open_decoder(&p).map_err(AppError::Decode)?;
+    let (mut sum_sq, mut peak, mut n) = (0.0f64, 0.0f32, 0u64);
+    for s in dec.convert_samples::<f32>() {
+        sum_sq += (s as f64) * (s as f64);
+        peak = peak.max(s.abs());
+        n += 1;
+    }
+    if n == 0 || peak == 0.0 {
+        return Ok(1.0);
+    }
+    let rms = (sum_sq / n as f64).sqrt();
+    let gain = ((LOUDNESS_TARGET_RMS / rms) as f32).min(1.0 / peak);
+    Ok(gain.clamp(LOUDNESS_GAIN_MIN, LOUDNESS_GAIN_MAX))
+}
+
+// Replace the current BGM with a user-supplied file (looped). The header is probed here so
+// unreadable/undecodable files come back as an error; the audio thread then streams it.
+// Same formats as `play_sfx_file`.
+#[tauri::command]
+fn bgm_play_file(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AudioTx>,
+    path: String,
+    volume: f32,
+    muted: bool,
+) -> Result<(), AppError> {
+    let p = resolve_user_audio_path(&app, &path)?;
+    open_decoder(&p).map_err(AppError::Decode)?;
+    let volume = sanitize_gain(volume).min(1.0);
+    if muted || volume <= 0.0001 {
+        return Ok(());
+    }
+    state.send(AudioMsg::BgmPlayFile { path: p, volume })
+}
+
+// How long a command waits for the audio thread to answer a query.
+const AUDIO_QUERY_TIMEOUT: Duration = Duration::from_millis(250);
+
+// Why a query came back empty: the thread is gone, or just didn't answer in time.
+fn no_audio_reply(what: &str) -> AppError {
+    if !AUDIO_ALIVE.load(Ordering::Relaxed) {
+        return audio_shut_down();
+    }
+    AppError::AudioSend(format!("{what}: audio thread did not respond"))
+}
+
+// Ask the audio thread something and wait briefly for the answer. None if the thread is
+// gone or too busy to reply in time.
+fn query_audio<T>(tx: &AudioTx, make: impl FnOnce(Sender<T>) -> AudioMsg) -> Option<T> {
+    let (reply, rx) = crossbeam_channel::bounded(1);
+    tx.send(make(reply)).ok()?;
+    rx.recv_timeout(AUDIO_QUERY_TIMEOUT).ok()
+}
+
+// Position of the current BGM in ms, for syncing visuals to the music; 0 when nothing is
+// playing. Looping tracks wrap back to 0 at the end of the track when its length is known;
+// otherwise (e.g. OGG, intro+loop tracks) this is total play time since the track started.
+#[tauri::command]
+fn bgm_position_ms(state: tauri::State<'_, AudioTx>) -> u64 {
+    query_audio(&state, |reply| AudioMsg::QueryBgmPosition { reply })
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// Whether BGM is playing right now (started and not paused; a globally muted track still
+// counts). False if the audio thread can't be reached.
+#[tauri::command]
+fn bgm_is_playing(state: tauri::State<'_, AudioTx>) -> bool {
+    query_audio(&state, |reply| AudioMsg::QueryBgm { reply }).unwrap_or(false)
+}
+
+// Everything the mixer knows right now (bus volumes, mute, current track, playing/paused,
+// live SFX count, SFX dropped under load), for attaching to bug reports alongside the log.
+#[tauri::command]
+fn audio_state(state: tauri::State<'_, AudioTx>) -> Result<AudioState, AppError> {
+    query_audio(&state, |reply| AudioMsg::QueryState { reply })
+        .ok_or_else(|| no_audio_reply("audio state"))
+}
+
+// Decode failures, sink creation failures, dropped SFX and device rebuilds since launch.
+// Goes through the audio thread so the numbers include everything sent before the call.
+#[tauri::command]
+fn audio_stats(state: tauri::State<'_, AudioTx>) -> Result<AudioStats, AppError> {
+    query_audio(&state, |reply| AudioMsg::QueryStats { reply })
+        .ok_or_else(|| no_audio_reply("audio stats"))
+}
+
+const BGM_SPEED_MIN: f32 = 0.25;
+const BGM_SPEED_MAX: f32 = 4.0;
+
+// Slow down / speed up the music (e.g. slow-motion mode); 1.0 is normal. Pitch follows speed.
+#[tauri::command]
+fn bgm_speed(state: tauri::State<'_, AudioTx>, factor: f32) -> Result<(), AppError> {
+    let factor = if factor.is_finite() { factor.clamp(BGM_SPEED_MIN, BGM_SPEED_MAX) } else { 1.0 };
+    state.send(AudioMsg::BgmSpeed { factor })
+}
+
+// Jump the current BGM to `ms` (e.g. for a music preview screen). Sources that can't seek
+// come back as "unsupported", a decoder that fails mid-seek as "decode"; with no BGM playing
+// this does nothing. Off the main thread, as it
+// waits for the audio thread's answer.
+#[tauri::command(async)]
+fn bgm_seek(state: tauri::State<'_, AudioTx>, ms: u64) -> Result<(), AppError> {
+    let pos = Duration::from_millis(ms);
+    query_audio(&state, |reply| AudioMsg::BgmSeek { pos, reply })
+        .ok_or_else(|| no_audio_reply("bgm seek"))?
+}
+
+const LOWPASS_MIN_HZ: f32 = 20.0;
+const LOWPASS_MAX_HZ: f32 = 20_000.0;
+
+// Muffle the mix (pause screen, underwater...) with a low-pass at `cutoff_hz`. BGM is always
+// filtered; `sfx` (default false) filters new SFX too. `enabled: false` removes the filter.
+#[tauri::command]
+fn set_lowpass(state: tauri::State<'_, AudioTx>, enabled: bool, cutoff_hz: f32, sfx: Option<bool>) -> Result<(), AppError> {
+    let filter = if enabled {
+        if !cutoff_hz.is_finite() {
+            return Err(AppError::InvalidInput(format!("invalid cutoff_hz: {cutoff_hz}")));
+        }
+        let cutoff_hz = cutoff_hz.clamp(LOWPASS_MIN_HZ, LOWPASS_MAX_HZ).round() as u32;
+        Some(LowpassFilter { cutoff_hz, sfx: sfx.unwrap_or(false) })
+    } else {
+        None
+    };
+    state.send(AudioMsg::Lowpass { filter })
+}
+
+// Audition an embedded track (looped, at `volume` on the BGM bus) for a music-select screen.
+// The playing BGM is paused meanwhile and picks up where it was on `bgm_preview_stop`; a new
+// preview replaces the last one. Neither changes which track `bgm_play` & co. consider current.
+#[tauri::command]
+fn bgm_preview(state: tauri::State<'_, AudioTx>, track: String, volume: f32) -> Result<(), AppError> {
+    if bgm_track_bytes(&track).is_none() {
+        return Err(AppError::InvalidInput(format!("unknown bgm track: {track}")));
+    }
+    state.send(AudioMsg::BgmPreview { track, volume: sanitize_gain(volume).min(1.0) })
+}
+
+#[tauri::command]
+fn bgm_preview_stop(state: tauri::State<'_, AudioTx>) -> Result<(), AppError> {
+    state.send(AudioMsg::BgmPreviewStop)
+}
+
+// Pause/resume keep the sink (and its position); both are no-ops when no BGM is active.
+#[tauri::command]
+fn bgm_pause(state: tauri::State<'_, AudioTx>) -> Result<(), AppError> {
+    state.send(AudioMsg::BgmPause)
+}
+
+#[tauri::command]
+fn bgm_resume(state: tauri::State<'_, AudioTx>) -> Result<(), AppError> {
+    state.send(AudioMsg::BgmResume)
+}
+
+// Sum BGM and SFX to mono (for single-sided hearing); panned sounds collapse to the center.
+// Takes effect immediately, including on sounds already playing. Off by default.
+#[tauri::command]
+fn set_mono_output(enabled: bool) {
+    MONO_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+// Pause BGM while the window is unfocused and pick it back up on focus (off by default). Music
+// paused with `bgm_pause` stays paused when focus returns.
+#[tauri::command]
+fn set_pause_on_blur(state: tauri::State<'_, PauseOnBlur>, enabled: bool) {
+    state.0.store(enabled, Ordering::Relaxed);
+}
+
+// `muted` silences the music but keeps it playing, so unmuting carries on from the same
+// spot; use `bgm_stop` to actually stop it. Fine to call on every slider move: updates that
+// pile up are coalesced on the audio thread. The volume is saved for the next launch.
+#[tauri::command]
+fn bgm_volume(
+    state: tauri::State<'_, AudioTx>,
+    music: tauri::State<'_, Arc<MusicVolume>>,
+    volume: f32,
+    muted: bool,
+) -> Result<(), AppError> {
+    let volume = sanitize_gain(volume).min(1.0);
+    state.send(AudioMsg::BgmVolume { volume })?;
+    state.send(AudioMsg::BgmMuted { muted })?;
+    music.set(volume);
+    Ok(())
+}
+
+// Run volume sliders through a perceptual (square-law) curve. Off by default, so existing
+// volumes keep their linear gains (0.45 stays 0.45 rather than dropping to about 0.2).
+#[tauri::command]
+fn set_perceptual_volume(state: tauri::State<'_, AudioTx>, enabled: bool) -> Result<(), AppError> {
+    state.send(AudioMsg::PerceptualVolume { enabled })
+}
+
+// Quieten gameplay SFX to `amount` (0..1) of their volume, e.g. while a settings panel is
+// open; 1.0 resets. Separate from the BGM duck, and "ui" sounds are never affected.
+#[tauri::command]
+fn set_sfx_duck(state: tauri::State<'_, AudioTx>, amount: f32) -> Result<(), AppError> {
+    let amount = if amount.is_finite() { amount.clamp(0.0, 1.0) } else { 1.0 };
+    state.send(AudioMsg::SfxDuck { amount })
+}
+
+// Pause-menu audio in one call: the music ducks, slows slightly and is muffled, and gameplay
+// SFX duck, all ramping in together. `audio_exit_pause` ramps back and restores the low-pass
+// and SFX duck that were set before. Entering twice (or exiting while not paused) does nothing.
+// Independent of `bgm_pause`, which stops the music outright.
+#[tauri::command]
+fn audio_enter_pause(state: tauri::State<'_, AudioTx>) -> Result<(), AppError> {
+    state.send(AudioMsg::EnterPause)
+}
+
+#[tauri::command]
+fn audio_exit_pause(state: tauri::State<'_, AudioTx>) -> Result<(), AppError> {
+    state.send(AudioMsg::ExitPause)
+}
+
+// Soft limiter on SFX so overlapping/boosted sounds saturate smoothly instead of clipping.
+// On by default; applies to sounds started after the call.
+#[tauri::command]
+fn set_sfx_limiter(state: tauri::State<'_, AudioTx>, enabled: bool) -> Result<(), AppError> {
+    state.send(AudioMsg::SfxLimiter { enabled })
+}
+
+// Continuous engine hum while boosting: start it, feed it the snake's speed (0..1) as it
+// changes, and stop it (with a short fade) when the boost ends. Start while running is a no-op.
+#[tauri::command]
+fn boost_loop_start(state: tauri::State<'_, AudioTx>) -> Result<(), AppError> {
+    state.send(AudioMsg::BoostLoopStart)
+}
+
+#[tauri::command]
+fn boost_loop_set(state: tauri::State<'_, AudioTx>, speed: f32) -> Result<(), AppError> {
+    let speed = if speed.is_finite() { speed.clamp(0.0, 1.0) } else { 0.0 };
+    state.send(AudioMsg::BoostLoopSet { speed })
+}
+
+#[tauri::command]
+fn boost_loop_stop(state: tauri::State<'_, AudioTx>) -> Result<(), AppError> {
+    state.send(AudioMsg::BoostLoopStop)
+}
+
+// Loop an embedded track as ambience under the music, on its own sink: BGM plays, stops and
+// crossfades without touching it. Follows master volume and mute. Playing the track that's
+// already looping just changes its volume.
+#[tauri::command]
+fn ambient_play(state: tauri::State<'_, AudioTx>, track: String, volume: f32) -> Result<(), AppError> {
+    if bgm_track_bytes(&track).is_none() {
+        return Err(AppError::InvalidInput(format!("unknown ambient track: {track}")));
+    }
+    let volume = sanitize_gain(volume).min(1.0);
+    state.send(AudioMsg::AmbientPlay { track, volume })
+}
+
+#[tauri::command]
+fn ambient_stop(state: tauri::State<'_, AudioTx>) -> Result<(), AppError> {
+    state.send(AudioMsg::AmbientStop)
+}
+
+// Mute everything from one place. The per-call `muted` flags on the play/volume commands
+// still work, but this one wins: while it's set nothing is heard whatever they say. BGM keeps
+// its position and comes back at its current volume on unmute; SFX played meanwhile are dropped.
+#[tauri::command]
+fn set_muted(sfx: tauri::State<'_, SfxTx>, muted: bool) -> Result<(), AppError> {
+    sfx.send_ordered(AudioMsg::SetMuted { muted })
+}
+
+// The whole mixer setup, for `apply_audio_config`. Bus volumes are 0..1 (out of range is
+// clamped, non-numbers reset to 1.0); `device` None keeps the current output.
+#[derive(Debug, Clone, serde::Deserialize)]
+struct AudioConfig {
+    master_volume: f32,
+    sfx_volume: f32,
+    bgm_volume: f32,
+    muted: bool,
+    device: Option<String>,
+}
+
+// Set buses, mute and device in one go, so loading saved settings doesn't pass through
+// half-applied (too loud / too quiet) states the way separate calls would.
+#[tauri::command]
+fn apply_audio_config(state: tauri::State<'_, AudioTx>, cfg: AudioConfig) -> Result<(), AppError> {
+    state.send(AudioMsg::ApplyConfig { config: cfg })
+}
+
+// Mixer control for the settings UI: `bus` is "master", "sfx" or "bgm", `volume` 0..1.
+#[tauri::command]
+fn set_bus_volume(state: tauri::State<'_, AudioTx>, bus: Bus, volume: f32) -> Result<(), AppError> {
+    state.send(AudioMsg::SetBus { bus, volume: sanitize_gain(volume).min(1.0) })
+}
+
+// Appended to the default device's name in `list_audio_devices`.
+const DEFAULT_DEVICE_MARKER: &str = " (default)";
+
+// Output device names from the system audio host; the default one ends in " (default)".
+// Read-only: the running stream keeps using whatever device it opened.
+#[tauri::command]
+fn list_audio_devices() -> Result<Vec<String>, AppError> {
+    let host = rodio::cpal::default_host();
+    let default_name = host.default_output_device().and_then(|d| d.name().ok());
+    let devices = host.output_devices().map_err(|e| AppError::Device(format!("output_devices: {e}")))?;
+    Ok(devices
+        .filter_map(|d| d.name().ok())
+        .map(|name| {
+            if Some(&name) == default_name.as_ref() {
+                format!("{name}{DEFAULT_DEVICE_MARKER}")
+            } else {
+                name
+            }
+        })
+        .collect())
+}
+
+// Move audio to the named output device (as listed by `list_audio_devices`). Falls back to
+// the default device, with an "audio-error" event, if it's no longer there.
+#[tauri::command]
+fn set_audio_device(state: tauri::State<'_, AudioTx>, name: String) -> Result<(), AppError> {
+    state.send(AudioMsg::SetDevice { name })
+}
+
+// Stop all audio and end the audio thread (dropping the output stream), e.g. before
+// re-initialising on another device. With `join`, waits for the thread to finish (off the
+// main thread).
+#[tauri::command(async)]
+fn audio_shutdown(tx: tauri::State<'_, AudioTx>, thread: tauri::State<'_, AudioThread>, join: Option<bool>) -> Result<(), AppError> {
+    // Already gone is fine: there's nothing left to shut down.
+    let _ = tx.0.send(AudioMsg::Shutdown);
+    if join.unwrap_or(false) {
+        let handle = thread.0.lock()?.take();
+        if let Some(h) = handle {
+            h.join().map_err(|_| AppError::Internal("audio thread panicked".to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+// Structured log entry: one JSON object per line in ultimate-snake.jsonl, as
+// {"ts", "session", "frame", "entry"}. `frame` is the game's frame counter, if it has one.
+// The entry is serialized here, so a bad value is rejected before anything touches the file.
+#[tauri::command]
+fn log_json(log: tauri::State<'_, LogTx>, entry: serde_json::Value, frame: Option<u64>) -> Result<(), AppError> {
+    let line = serde_json::to_string(&serde_json::json!({
+        "ts": iso8601_now(),
+        "session": current_session_id(),
+        "frame": frame,
+        "entry": entry,
+    }))
+    .map_err(|e| AppError::InvalidInput(format!("serialize log entry: {e}")))?;
+    log.0.send(LogMsg::Json(line)).map_err(AppError::from)
+}
+
+// How long log commands wait for the writer thread to answer.
+const LOG_QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn query_log<T>(log: &LogTx, make: impl FnOnce(Sender<T>) -> LogMsg) -> Option<T> {
+    let (reply, rx) = crossbeam_channel::bounded(1);
+    log.0.send(make(reply)).ok()?;
+    rx.recv_timeout(LOG_QUERY_TIMEOUT).ok()
+}
+
+// Empty the log (creating it if needed), e.g. before reproducing a bug. Goes through the
+// writer thread so lines queued before the call don't reappear after it.
+#[tauri::command]
+fn clear_log(log: tauri::State<'_, LogTx>) -> Result<(), AppError> {
+    query_log(&log, |reply| LogMsg::Clear { reply })
+        .ok_or_else(|| AppError::LogSend("clear log: log writer did not respond".to_string()))?
+        .map_err(AppError::Io)
+}
+
+// Last `n` lines of the file at `path`, read backwards in chunks so a big log isn't loaded
+// whole.
+fn read_tail_lines(path: &std::path::Path, n: usize) -> std::io::Result<Vec<String>> {
+    const CHUNK: u64 = 8 * 1024;
+    let mut f = File::open(path)?;
+    let mut pos = f.metadata()?.len();
+    // Chunks from the end backwards; only each new chunk is scanned for newlines.
+    let mut chunks: Vec<Vec<u8>> = Vec::new();
+    let mut newlines = 0;
+
+    // Stop once there are more than `n` newlines: with a trailing newline, that's `n` full
+    // lines plus a possibly partial one in front of them, which gets dropped below.
+    while pos > 0 && newlines <= n {
+        let step = CHUNK.min(pos);
+        pos -= step;
+        f.seek(std::io::SeekFrom::Start(pos))?;
+        let mut chunk = vec![0; step as usize];
+        f.read_exact(&mut chunk)?;
+        newlines += chunk.iter().filter(|&&b| b == b'\n').count();
+        chunks.push(chunk);
+    }
+    chunks.reverse();
+    let buf = chunks.concat();
+
+    let text = String::from_utf8_lossy(&buf);
+    let lines: Vec<&str> = text.lines().collect();
+    let skip = lines.len().saturating_sub(n);
+    Ok(lines[skip..].iter().map(|l| l.to_string()).collect())
+}
+
+// Most recent `lines` log lines, oldest first, for the diagnostics panel. No log yet = empty.
+// Off the main thread, since it waits for the writer to flush first.
+#[tauri::command(async)]
+fn read_log_tail(app: tauri::AppHandle, log: tauri::State<'_, LogTx>, lines: usize) -> Result<Vec<String>, AppError> {
+    // Best effort: if the writer doesn't answer, read whatever has reached the disk.
+    let _ = query_log(&log, |reply| LogMsg::Flush { reply });
+    let path = log_file_path(&app)?;
+    let mut tail = match read_tail_lines(&path, lines) {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(AppError::Io(format!("read log: {e}"))),
+    };
+    // Just after a rotation the active log is short; the rest comes from the rotated one.
+    if tail.len() < lines {
+        let older = read_rotated_log_lines(&path).map_err(|e| AppError::Io(format!("read rotated log: {e}")))?;
+        let skip = older.len().saturating_sub(lines - tail.len());
+        tail.splice(0..0, older.into_iter().skip(skip));
+    }
+    Ok(tail)
+}
+
+// Bundle the logs for a bug report: the text log, its rotated file (gzipped, or a leftover
+// uncompressed one) and the JSONL log (any that exist), plus a meta.txt with OS, app version and audio device. `dest` is the zip
+// path, or a directory to put "ultimate-snake-logs-<session>.zip" in. Returns the zip's path.
+// Runs off the main thread: it flushes, reads and compresses every log.
+#[tauri::command(async)]
+fn export_logs(app: tauri::AppHandle, log: tauri::State<'_, LogTx>, audio: tauri::State<'_, AudioTx>, dest: String) -> Result<String, AppError> {
+    // Best effort, as in read_log_tail: get buffered lines onto disk first.
+    let _ = query_log(&log, |reply| LogMsg::Flush { reply });
+    let log_path = log_file_path(&app)?;
+
+    let mut dest = PathBuf::from(dest);
+    if dest.is_dir() {
+        dest = dest.join(format!("ultimate-snake-logs-{}.zip", current_session_id()));
+    }
+    let file = File::create(&dest).map_err(|e| AppError::Io(format!("create {}: {e}", dest.display())))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    let candidates = [
+        log_path.clone(),
+        compressed_log_path(&log_path),
+        rotated_log_path(&log_path),
+        log_path.with_file_name(JSON_LOG_FILE_NAME),
+    ];
+    for path in candidates.iter().filter(|p| p.is_file()) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let bytes = std::fs::read(path).map_err(|e| AppError::Io(format!("read {}: {e}", path.display())))?;
+        zip.start_file(name, options).map_err(|e| AppError::Io(format!("zip: {e}")))?;
+        zip.write_all(&bytes).map_err(|e| AppError::Io(format!("zip: {e}")))?;
+    }
+
+    // The device the audio thread is really on (after `set_audio_device` or a fallback), not
+    // the system default.
+    let (device, rate) = match query_audio(&audio, |reply| AudioMsg::QueryState { reply }) {
+        Some(state) => (state.device.unwrap_or_else(|| "<unknown>".to_string()), state.sample_rate.to_string()),
+        None => ("<audio not running>".to_string(), "<unknown>".to_string()),
+    };
+    let meta = format!(
+        "os: {} {}\napp version: {}\nsession: {}\nexported: {}\naudio device: {}\naudio sample rate: {}\n",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        app.package_info().version,
+        current_session_id(),
+        iso8601_now(),
+        device,
+        rate,
+    );
+    zip.start_file("meta.txt", options).map_err(|e| AppError::Io(format!("zip: {e}")))?;
+    zip.write_all(meta.as_bytes()).map_err(|e| AppError::Io(format!("zip: {e}")))?;
+    zip.finish().map_err(|e| AppError::Io(format!("zip: {e}")))?;
+
+    Ok(dest.to_string_lossy().to_string())
+}
+
+// The log file this session writes to.
+#[tauri::command]
+fn log_path(app: tauri::AppHandle) -> Result<String, AppError> {
+    Ok(log_file_path(&app)?.to_string_lossy().to_string())
+}
+
+// Linear ramp between two gains; shared by every timed volume change on the audio thread.
+#[derive(Debug, Clone, Copy)]
+struct Ramp {
+    from: f32,
+    to: f32,
+    start: Instant,
+    dur: Duration,
+}
+
+impl Ramp {
+    fn new(from: f32, to: f32, dur: Duration) -> Self {
+        Self { from, to, start: Instant::now(), dur }
+    }
+
+    fn value(&self, now: Instant) -> f32 {
+        if self.dur.is_zero() {
+            return self.to;
+        }
+        let t = (now.duration_since(self.start).as_secs_f32() / self.dur.as_secs_f32()).min(1.0);
+        self.from + (self.to - self.from) * t
+    }
+
+    fn done(&self, now: Instant) -> bool {
+        now.duration_since(self.start) >= self.dur
+    }
+}
+
+// Fade-out of the whole mix when the app quits; quitting waits at most this (plus
+// QUIT_FADE_SLACK) for it.
+const QUIT_FADE: Duration = Duration::from_millis(150);
+const QUIT_FADE_SLACK: Duration = Duration::from_millis(50);
+
+// How long the menu SFX duck takes to move to a new amount, so opening a menu doesn't pop.
+const SFX_DUCK_RAMP: Duration = Duration::from_millis(120);
+
+// Pause-menu transition: BGM drops to PAUSE_BGM_DUCK of its volume and slows to
+// PAUSE_BGM_SPEED of its speed over PAUSE_RAMP, behind a low-pass at PAUSE_LOWPASS_HZ, while
+// gameplay SFX duck to PAUSE_SFX_DUCK.
+const PAUSE_RAMP: Duration = Duration::from_millis(300);
+const PAUSE_BGM_DUCK: f32 = 0.5;
+const PAUSE_BGM_SPEED: f32 = 0.94;
+const PAUSE_LOWPASS_HZ: u32 = 1200;
+const PAUSE_SFX_DUCK: f32 = 0.3;
+
+// Decoded embedded BGM tracks kept for reuse: enough for the current track and one it's
+// crossfading with or switching back to. Rebuilding a sink (low-pass change, device switch,
+// preview) then skips decoding the whole track again on the audio thread, which would stall
+// every other sound meanwhile. A decoded track is tens of MB, hence the small bound; sinks
+// still playing an evicted track keep their own reference.
+const BGM_CLIP_CACHE_SIZE: usize = 2;
+
+// How often the audio thread wakes up while something is ramping. Idle, it just blocks on recv.
+const AUDIO_TICK: Duration = Duration::from_millis(10);
+
+// Loud SFX that duck the music: BGM drops to `duck_level` of its volume, then ramps back
+// over `duck_release_ms` (see AudioTuning).
+const DUCKING_SFX: &[&str] = &["death", "poison"];
+
+// Where the current BGM comes from.
+#[derive(Debug, Clone)]
+enum BgmSource {
+    // `looping: false` plays once; the engine drops it when it ends.
+    Track { name: String, loop_points: Option<(u64, u64)>, looping: bool },
+    File(PathBuf),
+}
+
+impl BgmSource {
+    // Track name, or the file path for user BGM.
+    fn label(&self) -> String {
+        match self {
+            BgmSource::Track { name, .. } => name.clone(),
+            BgmSource::File(path) => path.display().to_string(),
+        }
+    }
+}
+
+// Global "muffled" filter: a low-pass at `cutoff_hz` on BGM, and on SFX too when `sfx` is set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LowpassFilter {
+    cutoff_hz: u32,
+    sfx: bool,
+}
+
+// Append `src` to `sink`, through a low-pass at `cutoff_hz` if one is given, metering what
+// comes out into `meter`. Without a cutoff the source goes in untouched, so a disabled filter
+// changes nothing.
+fn append_filtered<S>(sink: &Sink, src: S, cutoff_hz: Option<u32>, meter: &Arc<LevelMeter>)
+where
+    S: Source<Item = f32> + Send + 'static,
+{
+    match cutoff_hz {
+        Some(hz) => sink.append(LevelTap::new(MonoMix::new(src.low_pass(hz)), meter.clone())),
+        None => sink.append(LevelTap::new(MonoMix::new(src), meter.clone())),
+    }
+}
+
+// Open an output stream on the device called `name`.
+fn open_output_device(name: &str) -> Result<(OutputStream, OutputStreamHandle), String> {
+    let host = rodio::cpal::default_host();
+    let device = host
+        .output_devices()
+        .map_err(|e| format!("output_devices: {e}"))?
+        .find(|d| d.name().is_ok_and(|n| n == name))
+        .ok_or_else(|| format!("no output device named {name:?}"))?;
+    OutputStream::try_from_device(&device).map_err(|e| format!("{name}: {e}"))
+}
+
+// Where the engine's sinks play. The real backend is a rodio output stream; tests swap in
+// one whose sinks go nowhere and that records each SFX instead.
+trait AudioBackend {
+    fn new_sink(&self) -> Result<Sink, PlayError>;
+    // Device the sinks play on (None if unknown, which disables the presence check).
+    fn device_name(&self) -> Option<String>;
+    // Rate procedural sounds are generated at.
+    fn sample_rate(&self) -> u32;
+    // Called for each SFX as it's queued, with its final gain and pan.
+    fn sfx_played(&self, _kind: &str, _gain: f32, _pan: f32) {}
+}
+
+struct RodioBackend {
+    // Keeps the device stream alive; replaced when switching devices.
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    device_name: Option<String>,
+    // Set when the stream was opened with a config other than the device's default.
+    sample_rate: Option<u32>,
+}
+
+impl RodioBackend {
+    fn open(name: &str) -> Result<Self, String> {
+        let (stream, handle) = open_output_device(name)?;
+        Ok(Self { _stream: stream, handle, device_name: Some(name.to_string()), sample_rate: None })
+    }
+
+    // The first output that opens: the default device, then every other one, each with its
+    // default config and then each config it says it supports (at that config's top rate).
+    // Failed attempts go to stderr; the error sums them up.
+    fn open_any() -> Result<Self, String> {
+        let host = rodio::cpal::default_host();
+        let mut devices: Vec<rodio::cpal::Device> = host.default_output_device().into_iter().collect();
+        let default_name = default_output_device_name();
+        match host.output_devices() {
+            Ok(all) => devices.extend(all.filter(|d| d.name().ok() != default_name)),
+            Err(e) => eprintln!("audio OutputStream: output_devices: {e}"),
+        }
+        if devices.is_empty() {
+            return Err("no output devices found".to_string());
+        }
+
+        let mut attempts = 0;
+        for device in &devices {
+            let name = device.name().unwrap_or_else(|_| "unnamed device".to_string());
+            attempts += 1;
+            match OutputStream::try_from_device(device) {
+                Ok((stream, handle)) => {
+                    return Ok(Self { _stream: stream, handle, device_name: device.name().ok(), sample_rate: None });
+                }
+                Err(e) => eprintln!("audio OutputStream: {name} (default config): {e}"),
+            }
+            let configs = match device.supported_output_configs() {
+                Ok(c) => c,
+                Err(e) => { eprintln!("audio OutputStream: {name} configs: {e}"); continue; }
+            };
+            for range in configs {
+                let config = range.with_max_sample_rate();
+                let rate = config.sample_rate().0;
+                attempts += 1;
+                match OutputStream::try_from_device_config(device, config) {
+                    Ok((stream, handle)) => {
+                        eprintln!("audio OutputStream: using {name} at {rate}Hz");
+                        return Ok(Self { _stream: stream, handle, device_name: device.name().ok(), sample_rate: Some(rate) });
+                    }
+                    Err(e) => eprintln!("audio OutputStream: {name} at {rate}Hz: {e}"),
+                }
+            }
+        }
+        Err(format!("no output device would open ({attempts} attempts on {} devices)", devices.len()))
+    }
+}
+
+impl AudioBackend for RodioBackend {
+    fn new_sink(&self) -> Result<Sink, PlayError> {
+        Sink::try_new(&self.handle)
+    }
+
+    fn device_name(&self) -> Option<String> {
+        self.device_name.clone()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate.unwrap_or_else(|| output_sample_rate(self.device_name.as_deref()))
+    }
+}
+
+fn default_output_device_name() -> Option<String> {
+    rodio::cpal::default_host().default_output_device().and_then(|d| d.name().ok())
+}
+
+// Rate procedural sounds are generated at when the device's can't be determined.
+const FALLBACK_SAMPLE_RATE: u32 = 48_000;
+
+// Sample rate the output stream runs at. rodio opens devices with their default output
+// config, so that's what we read back (for `name`, or the default device).
+fn output_sample_rate(name: Option<&str>) -> u32 {
+    let host = rodio::cpal::default_host();
+    let device = match name {
+        Some(name) => host.output_devices().ok().and_then(|mut ds| ds.find(|d| d.name().is_ok_and(|n| n == name))),
+        None => host.default_output_device(),
+    };
+    device
+        .and_then(|d| d.default_output_config().ok())
+        .map(|c| c.sample_rate().0)
+        .unwrap_or(FALLBACK_SAMPLE_RATE)
+}
+
+// Whether `name` is still listed. If the host can't be queried we assume it is, so a flaky
+// query doesn't tear down a working stream.
+fn output_device_exists(name: &str) -> bool {
+    match rodio::cpal::default_host().output_devices() {
+        Ok(mut devices) => devices.any(|d| d.name().is_ok_and(|n| n == name)),
+        Err(_) => true,
+    }
+}
+
+// Device watchdog: how often to check the open device is still there, and the retry backoff
+// for rebuilding the stream once it's gone.
+const DEVICE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const DEVICE_RETRY_MIN: Duration = Duration::from_millis(250);
+const DEVICE_RETRY_MAX: Duration = Duration::from_secs(5);
+
+// The device the audio thread is playing on, for `device_watch_thread`. None while unknown or
+// while the engine is already recovering.
+static WATCHED_DEVICE: Mutex<Option<String>> = Mutex::new(None);
+
+fn set_watched_device(name: Option<String>) {
+    if let Ok(mut watched) = WATCHED_DEVICE.lock() {
+        *watched = name;
+    }
+}
+
+// Checks every DEVICE_CHECK_INTERVAL that the open device is still listed and tells the audio
+// thread when it isn't. Enumerating devices can take a good part of a second on ALSA/Pulse, so
+// it runs here rather than holding up queued sounds on the audio thread.
+fn device_watch_thread(tx: Sender<AudioMsg>) {
+    loop {
+        std::thread::sleep(DEVICE_CHECK_INTERVAL);
+        if !AUDIO_ALIVE.load(Ordering::Relaxed) {
+            return;
+        }
+        let Some(name) = WATCHED_DEVICE.lock().ok().and_then(|n| n.clone()) else { continue };
+        if !output_device_exists(&name) && tx.send(AudioMsg::DeviceLost { name }).is_err() {
+            return;
+        }
+    }
+}
+
+// Length of a BGM source if its format reports one (often not for streamed OGG/MP3).
+// Intro+loop tracks have no single length, so they report None.
+fn bgm_source_length(src: &BgmSource) -> Option<Duration> {
+    match src {
+        BgmSource::Track { loop_points: Some(_), .. } => None,
+        BgmSource::Track { name, looping: false, .. } => Decoder::new(Cursor::new(bgm_track_bytes(name)?)).ok()?.total_duration(),
+        // Each wrap skips the crossfaded head, so one loop is that much shorter than the file.
+        BgmSource::Track { name, .. } => Decoder::new(Cursor::new(bgm_track_bytes(name)?))
+            .ok()?
+            .total_duration()
+            .map(|d| d.saturating_sub(BGM_LOOP_CROSSFADE)),
+        BgmSource::File(path) => open_decoder(path).ok()?.total_duration(),
+    }
+}
+
+// Everything the audio thread owns. Lives on that thread only (OutputStream isn't Send).
+struct AudioEngine {
+    // Output the sinks play on; replaced when switching devices.
+    backend: Box<dyn AudioBackend>,
+    // Name of the device `backend` is on (None if unknown, which disables the presence check).
+    device_name: Option<String>,
+    // Sample rate of that device; procedural sounds are generated at it.
+    output_rate: u32,
+    // Set while the device is gone: when to retry, and the current backoff.
+    recovery: Option<(Instant, Duration)>,
+    // Set by anything that fails to create a sink; the watchdog treats it as a lost device.
+    sink_failed: Cell<bool>,
+    // Recently started embedded BGM tracks, decoded, newest first (see `bgm_clip`).
+    bgm_clips: RefCell<Vec<(String, Arc<AudioClip>)>>,
+    events: AudioEvents,
+    rng: Rng,
+    bgm: Option<Sink>,
+    // What `bgm` is playing, so it can be restarted on a new device.
+    bgm_source: Option<BgmSource>,
+    // Cached `bgm_source_length` results, keyed by the source's debug form.
+    bgm_lengths: HashMap<String, Option<Duration>>,
+    bgm_vol: f32,
+    // `bgm_volume(muted)`: the music keeps playing at zero volume.
+    bgm_muted: bool,
+    // BGM was paused by the window losing focus (not by `bgm_pause`), so focus resumes it.
+    blur_paused: bool,
+    // Playback speed for BGM (1.0 = normal); kept across track changes.
+    bgm_speed: f32,
+    // Active "muffled" filter, if any; applied whenever a BGM or SFX source is built.
+    lowpass: Option<LowpassFilter>,
+    // Fade-in applied on top of the current BGM volume (e.g. the incoming side of a crossfade).
+    bgm_fade: Option<Ramp>,
+    // Previous BGM sinks fading down, each with its own base gain from when it was current (so
+    // the incoming track's volume and trim don't change it); stopped and dropped once their ramp
+    // ends.
+    bgm_outgoing: Vec<(Sink, f32, Ramp)>,
+    // `bgm_preview` sink with its track and volume, and whether it paused the main BGM (which
+    // it then resumes on stop, unless something else paused or resumed it in between).
+    bgm_audition: Option<(Sink, String, f32)>,
+    bgm_held: bool,
+    // Label of a just-started BGM whose "bgm-started" waits for the sink to start playing it.
+    bgm_start_pending: Option<String>,
+    // `bgm_switch_on_beat` waiting for its beat: (when, track, crossfade length).
+    beat_switch: Option<(Instant, String, Duration)>,
+    buses: BusVolumes,
+    // Gameplay SFX scale while a menu is open (`set_sfx_duck`); 1.0 = not ducked. Applied on
+    // the pool's gameplay sinks, ramping over SFX_DUCK_RAMP when it changes.
+    sfx_duck: f32,
+    sfx_duck_fade: Option<Ramp>,
+    // Set while in the pause-menu transition, holding the low-pass and SFX duck to restore on
+    // exit. The fade runs 0 (playing) to 1 (paused) or back and scales BGM volume and speed.
+    menu_pause: Option<(Option<LowpassFilter>, f32)>,
+    menu_pause_fade: Option<Ramp>,
+    // Soft-limit SFX voices (`set_sfx_limiter`); on by default.
+    sfx_limiter: bool,
+    // Global mute (`set_muted`): BGM keeps playing at 0 so it resumes in place; SFX are dropped.
+    muted: bool,
+    // Tunables from config.toml (`reload_config`).
+    tuning: AudioTuning,
+    // Active BGM duck, ramping from `tuning.duck_level` back to 1.0.
+    duck: Option<Ramp>,
+    // Continuous boost/engine loop, its current speed (0..1), and a stopped one fading out.
+    boost: Option<Sink>,
+    boost_speed: f32,
+    boost_outgoing: Option<(Sink, Ramp)>,
+    // Ambient loop (`ambient_play`) and the track it's playing, independent of BGM.
+    ambient: Option<(Sink, String)>,
+    ambient_vol: f32,
+    // Sound-test voice (`preview_sfx`), separate from the gameplay pool.
+    preview: Option<Sink>,
+    // Quit fade on the SFX sinks (`FadeOutAll`); BGM and boost use their own fades.
+    quit_fade: Option<Ramp>,
+    // Levels of BGM (before sink volume) and SFX (after gain), for "audio-level".
+    bgm_meter: Arc<LevelMeter>,
+    sfx_meter: Arc<LevelMeter>,
+    next_level_emit: Instant,
+    // Last "audio-level" was non-silent, so one more (zero) reading is owed once things stop.
+    level_active: bool,
+    sfx_pool: SfxPool,
+    // Decoded SFX, keyed by kind. Built once at startup; kinds that are missing (e.g. failed to
+    // decode) are decoded on demand and cached the first time they play.
+    sfx_cache: HashMap<String, Vec<AudioClip>>,
+    // Last time each kind actually played (for the per-kind cooldown).
+    sfx_last_played: HashMap<String, Instant>,
+    // Per-kind cooldowns (ms) set at runtime, over the config.toml / built-in ones.
+    sfx_cooldowns: HashMap<String, u64>,
+    // In-flight SFX by the id `play_sfx` returned.
+    active_sfx: HashMap<u64, SfxVoice>,
+    // `schedule_sfx` shots waiting for their time: (when, id, kind, volume).
+    scheduled_sfx: Vec<(Instant, u64, String, f32)>,
+}
+
+impl AudioEngine {
+    fn new(backend: Box<dyn AudioBackend>, events: AudioEvents) -> Result<Self, PlayError> {
+        let sfx_pool = SfxPool::new(backend.as_ref())?;
+
+        let mut sfx_cache = HashMap::new();
+        for kind in SFX_KINDS {
+            let clips = decode_sfx_kind(kind, &events);
+            if !clips.is_empty() {
+                sfx_cache.insert(kind.to_string(), clips);
+            }
+        }
+
+        Ok(Self {
+            device_name: backend.device_name(),
+            output_rate: backend.sample_rate(),
+            backend,
+            recovery: None,
+            sink_failed: Cell::new(false),
+            bgm_clips: RefCell::new(Vec::new()),
+            events,
+            rng: Rng::from_time(),
+            bgm: None,
+            bgm_source: None,
+            bgm_lengths: HashMap::new(),
+            bgm_vol: AudioTuning::default().default_music_volume,
+            bgm_muted: false,
+            blur_paused: false,
+            bgm_speed: 1.0,
+            lowpass: None,
+            bgm_fade: None,
+            bgm_outgoing: Vec::new(),
+            bgm_audition: None,
+            bgm_held: false,
+            bgm_start_pending: None,
+            beat_switch: None,
+            buses: BusVolumes { master: 1.0, sfx: 1.0, bgm: 1.0, perceptual: false },
+            sfx_duck: 1.0,
+            sfx_duck_fade: None,
+            menu_pause: None,
+            menu_pause_fade: None,
+            sfx_limiter: true,
+            muted: false,
+            tuning: AudioTuning::default(),
+            duck: None,
+            boost: None,
+            boost_speed: 0.0,
+            boost_outgoing: None,
+            ambient: None,
+            ambient_vol: 1.0,
+            preview: None,
+            quit_fade: None,
+            bgm_meter: Arc::default(),
+            sfx_meter: Arc::default(),
+            next_level_emit: Instant::now(),
+            level_active: false,
+            sfx_pool,
+            sfx_cache,
+            sfx_last_played: HashMap::new(),
+            sfx_cooldowns: HashMap::new(),
+            active_sfx: HashMap::new(),
+            scheduled_sfx: Vec::new(),
+        })
+    }
+
+    // How long the loop may block before the next tick is due; None = until a message arrives.
+    fn wake_after(&self, now: Instant) -> Option<Duration> {
+        if self.duck.is_some()
+            || self.bgm_fade.is_some()
+            || !self.bgm_outgoing.is_empty()
+            || self.boost_outgoing.is_some()
+            || self.quit_fade.is_some()
+            || self.sfx_duck_fade.is_some()
+            || self.menu_pause_fade.is_some()
+            || (self.bgm_start_pending.is_some() && self.bgm.as_ref().is_some_and(|s| !s.is_paused()))
+        {
+            // A scheduled SFX due sooner than the next tick still wakes the thread on time.
+            let tick = self.next_scheduled_sfx().map_or(AUDIO_TICK, |at| at.saturating_duration_since(now).min(AUDIO_TICK));
+            return Some(tick);
+        }
+        let device = self.recovery.map(|(at, _)| at);
+        let level = self.metering().then_some(self.next_level_emit);
+        let beat = self.beat_switch.as_ref().map(|(at, ..)| *at);
+        let deadline = device.into_iter().chain(level).chain(beat).chain(self.next_scheduled_sfx()).min()?;
+        Some(deadline.saturating_duration_since(now))
+    }
+
+    fn next_scheduled_sfx(&self) -> Option<Instant> {
+        self.scheduled_sfx.iter().map(|(at, ..)| *at).min()
+    }
+
+    // Play every scheduled SFX that's due, earliest first.
+    fn fire_scheduled_sfx(&mut self, now: Instant) {
+        if !self.scheduled_sfx.iter().any(|(at, ..)| *at <= now) {
+            return;
+        }
+        let (mut due, waiting) = std::mem::take(&mut self.scheduled_sfx).into_iter().partition::<Vec<_>, _>(|(at, ..)| *at <= now);
+        self.scheduled_sfx = waiting;
+        due.sort_by_key(|(at, ..)| *at);
+        for (_, id, kind, volume) in due {
+            self.play_sfx(id, kind, volume, 1.0, None, 0.0, 0, None, None, 0.0);
+        }
+    }
+
+    // Whether "audio-level" should keep ticking: something audible is (or just was) playing.
+    fn metering(&self) -> bool {
+        self.level_active
+            || self.bgm.as_ref().is_some_and(|s| !s.is_paused())
+            || self.boost.is_some()
+            || !self.active_sfx.is_empty()
+    }
+
+    // rodio mixes inside cpal's callback and doesn't expose the result, so this approximates it
+    // from the sources we feed in: BGM levels scaled by the live BGM sink volume, summed with
+    // SFX as uncorrelated signals for RMS, and peaks added as a worst case. Levels are measured
+    // as the mixer pulls samples, which runs a buffer ahead of what's audible.
+    fn emit_level(&mut self) {
+        self.active_sfx.retain(|_, v| !v.finished());
+        let gain = self.bgm.as_ref().map(|s| s.volume()).unwrap_or(0.0);
+        let (bgm_ms, bgm_peak) = self.bgm_meter.take();
+        let (sfx_ms, sfx_peak) = self.sfx_meter.take();
+        let rms = (bgm_ms * gain * gain + sfx_ms).sqrt();
+        let peak = bgm_peak * gain + sfx_peak;
+        self.level_active = rms > 0.0 || peak > 0.0;
+        self.events.emit("audio-level", AudioLevelPayload { rms, peak });
+    }
+
+    fn tick(&mut self, now: Instant) {
+        self.fire_scheduled_sfx(now);
+        if self.duck.is_some_and(|d| d.done(now)) {
+            self.duck = None;
+        }
+        if self.bgm_fade.is_some_and(|f| f.done(now)) {
+            self.bgm_fade = None;
+        }
+        if self.menu_pause_fade.is_some() {
+            if let Some(s) = &self.bgm {
+                s.set_speed(self.bgm_speed_now(now));
+            }
+            if self.menu_pause_fade.is_some_and(|f| f.done(now)) {
+                self.menu_pause_fade = None;
+            }
+        }
+        let muted = self.muted || self.bgm_muted;
+        self.bgm_outgoing.retain(|(sink, base, ramp)| {
+            if ramp.done(now) {
+                sink.stop();
+                return false;
+            }
+            sink.set_volume(if muted { 0.0 } else { base * ramp.value(now) });
+            true
+        });
+        self.apply_bgm_volume(now);
+        self.reap_finished_bgm();
+        self.check_bgm_started();
+        if self.beat_switch.as_ref().is_some_and(|(at, ..)| now >= *at) {
+            if let Some((_, track, fade)) = self.beat_switch.take() {
+                let src = BgmSource::Track { name: track, loop_points: None, looping: true };
+                self.crossfade_bgm(src, self.bgm_vol, fade);
+            }
+        }
+        if let Some((sink, ramp)) = &self.boost_outgoing {
+            if ramp.done(now) {
+                sink.stop();
+                self.boost_outgoing = None;
+            } else {
+                sink.set_volume(ramp.value(now));
+            }
+        }
+        if self.sfx_duck_fade.is_some_and(|r| r.done(now)) {
+            self.sfx_duck_fade = None;
+            self.apply_sfx_volume(now);
+        }
+        if self.sfx_duck_fade.is_some() || self.quit_fade.is_some() {
+            self.apply_sfx_volume(now);
+        }
+        if self.quit_fade.is_some() {
+            self.apply_ambient_volume(now);
+        }
+        if now >= self.next_level_emit {
+            self.next_level_emit = now + LEVEL_INTERVAL;
+            if self.metering() {
+                self.emit_level();
+            }
+        }
+        self.watch_device(now);
+    }
+
+    // "bgm-started" once the output has actually pulled samples from the new sink (its
+    // position moves), rather than when the track was queued. Dropped if the track went away
+    // first.
+    fn check_bgm_started(&mut self) {
+        if self.bgm_start_pending.is_none() {
+            return;
+        }
+        match &self.bgm {
+            Some(s) if s.get_pos().is_zero() => {}
+            Some(_) => {
+                if let Some(track) = self.bgm_start_pending.take() {
+                    self.events.emit("bgm-started", BgmTrackPayload { track });
+                }
+            }
+            None => self.bgm_start_pending = None,
+        }
+    }
+
+    // Drop a one-shot track once its sink runs dry. Polled from tick, which runs at least every
+    // LEVEL_INTERVAL while BGM plays.
+    fn reap_finished_bgm(&mut self) {
+        let Some(BgmSource::Track { name, looping: false, .. }) = &self.bgm_source else { return };
+        if !self.bgm.as_ref().is_some_and(|s| s.empty()) {
+            return;
+        }
+        let track = name.clone();
+        self.bgm = None;
+        self.bgm_source = None;
+        self.bgm_fade = None;
+        self.events.emit("bgm-finished", BgmTrackPayload { track });
+    }
+
+    fn watch_device(&mut self, now: Instant) {
+        if let Some((at, backoff)) = self.recovery {
+            if now >= at {
+                self.try_recover(now, backoff);
+            }
+            return;
+        }
+        if self.sink_failed.replace(false) {
+            self.device_lost(now, "sink creation failed");
+        }
+    }
+
+    fn device_lost(&mut self, now: Instant, reason: &str) {
+        set_watched_device(None);
+        self.events.error("audio device", format!("{reason}; trying to recover"));
+        self.events.emit(
+            "audio-device-lost",
+            AudioDevicePayload { device: self.device_name.clone(), reason: Some(reason.to_string()) },
+        );
+        self.recovery = Some((now + DEVICE_RETRY_MIN, DEVICE_RETRY_MIN));
+    }
+
+    fn try_recover(&mut self, now: Instant, backoff: Duration) {
+        let recovered = match RodioBackend::open_any() {
+            Ok(backend) => self.attach_output(Box::new(backend)),
+            Err(_) => false,
+        };
+        if recovered {
+            DEVICE_REBUILDS.fetch_add(1, Ordering::Relaxed);
+            self.recovery = None;
+            self.events.emit("audio-device-recovered", AudioDevicePayload { device: self.device_name.clone(), reason: None });
+        } else {
+            let backoff = (backoff * 2).min(DEVICE_RETRY_MAX);
+            self.recovery = Some((now + backoff, backoff));
+        }
+    }
+
+    // Current multiplier on the live BGM sink from fades + ducking.
+    fn bgm_envelope(&self, now: Instant) -> f32 {
+        let duck = self.duck.map(|d| d.value(now)).unwrap_or(1.0);
+        let fade = self.bgm_fade.map(|f| f.value(now)).unwrap_or(1.0);
+        let pause = 1.0 + (PAUSE_BGM_DUCK - 1.0) * self.menu_pause_level(now);
+        duck * fade * pause
+    }
+
+    // How far into the pause-menu transition we are: 0 = playing, 1 = fully paused.
+    fn menu_pause_level(&self, now: Instant) -> f32 {
+        let target = if self.menu_pause.is_some() { 1.0 } else { 0.0 };
+        self.menu_pause_fade.map(|f| f.value(now)).unwrap_or(target)
+    }
+
+    // `bgm_speed` slowed by the pause-menu transition.
+    fn bgm_speed_now(&self, now: Instant) -> f32 {
+        self.bgm_speed * (1.0 + (PAUSE_BGM_SPEED - 1.0) * self.menu_pause_level(now))
+    }
+
+    fn enter_menu_pause(&mut self) {
+        if self.menu_pause.is_some() {
+            return;
+        }
+        let now = Instant::now();
+        self.menu_pause_fade = Some(Ramp::new(self.menu_pause_level(now), 1.0, PAUSE_RAMP));
+        self.menu_pause = Some((self.lowpass, self.sfx_duck));
+        self.set_lowpass(Some(LowpassFilter { cutoff_hz: PAUSE_LOWPASS_HZ, sfx: false }));
+        self.set_sfx_duck(PAUSE_SFX_DUCK);
+        self.apply_bgm_volume(now);
+    }
+
+    fn exit_menu_pause(&mut self) {
+        let now = Instant::now();
+        let level = self.menu_pause_level(now);
+        let Some((lowpass, sfx_duck)) = self.menu_pause.take() else { return };
+        self.menu_pause_fade = Some(Ramp::new(level, 0.0, PAUSE_RAMP));
+        self.set_lowpass(lowpass);
+        self.set_sfx_duck(sfx_duck);
+        self.apply_bgm_volume(now);
+    }
+
+    // BGM volume through its bus and the track trim, before fades/ducking; 0 while muted.
+    fn bgm_base_gain(&self) -> f32 {
+        if self.muted || self.bgm_muted {
+            return 0.0;
+        }
+        let trim = match &self.bgm_source {
+            Some(BgmSource::Track { name, .. }) => bgm_trim(name),
+            _ => 1.0,
+        };
+        self.buses.bgm_gain(self.bgm_vol) * trim
+    }
+
+    fn apply_bgm_volume(&self, now: Instant) {
+        if let Some(s) = &self.bgm {
+            s.set_volume(self.bgm_base_gain() * self.bgm_envelope(now));
+        }
+        if let Some((s, track, volume)) = &self.bgm_audition {
+            s.set_volume(self.audition_gain(track, *volume));
+        }
+    }
+
+    fn audition_gain(&self, track: &str, volume: f32) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        self.buses.bgm_gain(volume) * bgm_trim(track)
+    }
+
+    fn start_audition(&mut self, track: String, volume: f32) {
+        if let Some((s, ..)) = self.bgm_audition.take() {
+            s.stop();
+        }
+        if let Some(s) = self.bgm.as_ref().filter(|s| !s.is_paused()) {
+            s.pause();
+            self.bgm_held = true;
+        }
+        let Some(sink) = self.start_bgm_sink(&track, None, true) else { return };
+        sink.set_volume(self.audition_gain(&track, volume));
+        self.bgm_audition = Some((sink, track, volume));
+    }
+
+    fn stop_audition(&mut self) {
+        if let Some((s, ..)) = self.bgm_audition.take() {
+            s.stop();
+        }
+        if std::mem::take(&mut self.bgm_held) {
+            if let Some(s) = &self.bgm {
+                s.play();
+            }
+        }
+    }
+
+    // Decoded samples of embedded track `name`, from the cache if it was started recently.
+    // None (after reporting) if it doesn't decode.
+    fn bgm_clip(&self, name: &str) -> Option<Arc<AudioClip>> {
+        let mut cache = self.bgm_clips.borrow_mut();
+        if let Some(i) = cache.iter().position(|(n, _)| n == name) {
+            let entry = cache.remove(i);
+            let clip = entry.1.clone();
+            cache.insert(0, entry);
+            return Some(clip);
+        }
+        let bytes = bgm_track_bytes(name)?;
+        let clip = match decode_reader(Cursor::new(bytes)) {
+            Ok(c) => Arc::new(c),
+            Err(e) => { self.decode_error("bgm Decoder", e); return None; }
+        };
+        cache.insert(0, (name.to_string(), clip.clone()));
+        cache.truncate(BGM_CLIP_CACHE_SIZE);
+        Some(clip)
+    }
+
+    // New BGM sink for embedded track `name`, created silent so the caller decides the volume.
+    // The track is decoded up front; with `loop_points` (ms) it loops only that section,
+    // otherwise the whole file, or plays it once without `looping`.
+    fn start_bgm_sink(&self, name: &str, loop_points: Option<(u64, u64)>, looping: bool) -> Option<Sink> {
+        let clip = self.bgm_clip(name)?;
+        let sink = match self.backend.new_sink() {
+            Ok(s) => s,
+            Err(e) => { self.sink_error("bgm Sink", e); return None; }
+        };
+        sink.set_volume(0.0);
+
+        if let Some((start_ms, end_ms)) = loop_points {
+            let whole = clip.clone();
+            match LoopedTrack::new(clip, start_ms, end_ms) {
+                Some(track) => append_filtered(&sink, track, self.bgm_cutoff(), &self.bgm_meter),
+                None => {
+                    self.events.error("bgm loop points", format!("{start_ms}..{end_ms}ms out of range; looping whole track"));
+                    let track = LoopedTrack::whole(whole)?;
+                    append_filtered(&sink, track, self.bgm_cutoff(), &self.bgm_meter);
+                }
+            }
+            return Some(sink);
+        }
+
+        // Decoded up front (rather than streamed with Decoder::new_looped) so the wrap can be
+        // crossfaded; see `LoopedTrack`. One-shot tracks (short jingles) get their own copy.
+        if !looping {
+            append_filtered(&sink, clip.to_source(), self.bgm_cutoff(), &self.bgm_meter);
+            return Some(sink);
+        }
+        let Some(track) = LoopedTrack::whole(clip) else {
+            self.decode_error("bgm Decoder", "track is empty");
+            return None;
+        };
+        append_filtered(&sink, track, self.bgm_cutoff(), &self.bgm_meter);
+        Some(sink)
+    }
+
+    // Silent sink playing `src`; None (after reporting) if it can't be opened.
+    fn open_bgm(&self, src: &BgmSource) -> Option<Sink> {
+        let sink = match src {
+            BgmSource::Track { name, loop_points, looping } => self.start_bgm_sink(name, *loop_points, *looping),
+            // User files are streamed from disk (see `FileLoop`) rather than decoded up front,
+            // since they can be arbitrarily long.
+            BgmSource::File(path) => {
+                let src = match FileLoop::open(path.clone(), self.events.clone()) {
+                    Ok(s) => s,
+                    Err(e) => { self.decode_error("bgm Decoder", e); return None; }
+                };
+                let sink = match self.backend.new_sink() {
+                    Ok(s) => s,
+                    Err(e) => { self.sink_error("bgm Sink", e); return None; }
+                };
+                sink.set_volume(0.0);
+                append_filtered(&sink, src.convert_samples(), self.bgm_cutoff(), &self.bgm_meter);
+                Some(sink)
+            }
+        }?;
+        sink.set_speed(self.bgm_speed_now(Instant::now()));
+        Some(sink)
+    }
+
+    fn bgm_cutoff(&self) -> Option<u32> {
+        self.lowpass.map(|f| f.cutoff_hz)
+    }
+
+    // rodio filters wrap a source when it's built and can't be reached once it's appended, so
+    // changing the filter on live BGM means rebuilding its sink: open the same source again
+    // with the new filter, seek it to where the old one was, carry over paused state and
+    // volume, then swap it in. Sources that can't seek restart from the top. New SFX pick the
+    // filter up as they're played; ones already playing finish as they are.
+    fn set_lowpass(&mut self, filter: Option<LowpassFilter>) {
+        if filter == self.lowpass {
+            return;
+        }
+        self.lowpass = filter;
+        let Some(src) = self.bgm_source.clone() else { return };
+        let pos = self.bgm_position();
+        let paused = self.bgm.as_ref().is_some_and(|s| s.is_paused());
+        let Some(sink) = self.open_bgm(&src) else { return };
+        // Best effort: a failed seek just means the rebuilt track plays from the top.
+        let _ = sink.try_seek(pos);
+        if paused {
+            sink.pause();
+        }
+        if let Some(old) = self.bgm.replace(sink) {
+            old.stop();
+        }
+        self.apply_bgm_volume(Instant::now());
+    }
+
+    // Make `src` the current BGM (no fade). Leaves nothing playing if it fails to open.
+    fn start_bgm(&mut self, src: BgmSource) {
+        self.bgm = self.open_bgm(&src);
+        self.bgm_start_pending = self.bgm.as_ref().map(|_| src.label());
+        self.bgm_source = self.bgm.as_ref().map(|_| src);
+    }
+
+    // User-supplied BGM replaces whatever is playing.
+    fn play_bgm_file(&mut self, path: PathBuf, volume: f32) {
+        self.beat_switch = None;
+        self.stop_bgm_now();
+        self.bgm_vol = volume;
+        self.start_bgm(BgmSource::File(path));
+        self.apply_bgm_volume(Instant::now());
+    }
+
+    fn sink_error(&self, context: &str, e: PlayError) {
+        SINK_FAILURES.fetch_add(1, Ordering::Relaxed);
+        self.events.error(context, e);
+        self.sink_failed.set(true);
+    }
+
+    fn decode_error(&self, context: &str, e: impl std::fmt::Display) {
+        DECODE_FAILURES.fetch_add(1, Ordering::Relaxed);
+        self.events.error(context, e);
+    }
+
+    // Move the engine onto a freshly opened stream and reattach everything to it. Sinks
+    // belong to a stream, so BGM restarts from the top; its volume and paused/playing state
+    // carry over. False (old stream kept) if the new one can't even make sinks.
+    fn attach_output(&mut self, backend: Box<dyn AudioBackend>) -> bool {
+        let pool = match SfxPool::new(backend.as_ref()) {
+            Ok(p) => p,
+            Err(e) => { SINK_FAILURES.fetch_add(1, Ordering::Relaxed); self.events.error("sfx Sink", e); return false; }
+        };
+
+        let bgm = self.bgm_source.clone();
+        let paused = self.bgm.as_ref().is_some_and(|s| s.is_paused());
+        let boosting = self.boost.is_some();
+        let ambient = self.ambient.as_ref().map(|(_, track)| track.clone());
+        let audition = self.bgm_audition.as_ref().map(|(_, track, volume)| (track.clone(), *volume));
+        let held = self.bgm_held;
+        self.shutdown();
+
+        self.device_name = backend.device_name();
+        self.output_rate = backend.sample_rate();
+        self.backend = backend;
+        set_watched_device(self.device_name.clone());
+        self.sfx_pool = pool;
+        self.sfx_pool.warm_up(self.output_rate);
+        self.apply_sfx_volume(Instant::now());
+
+        if boosting {
+            self.start_boost();
+        }
+        if let Some(track) = ambient {
+            self.start_ambient(track);
+        }
+        if let Some(src) = bgm {
+            self.start_bgm(src);
+            if paused {
+                if let Some(s) = &self.bgm {
+                    s.pause();
+                }
+            }
+            self.apply_bgm_volume(Instant::now());
+        }
+        if let Some((track, volume)) = audition {
+            self.start_audition(track, volume);
+            self.bgm_held = held;
+        }
+        true
+    }
+
+    // Rebuild the output stream on the device called `name` (default if it's gone).
+    fn set_device(&mut self, name: &str) {
+        let name = name.strip_suffix(DEFAULT_DEVICE_MARKER).unwrap_or(name);
+        let backend = match RodioBackend::open(name) {
+            Ok(backend) => backend,
+            Err(e) => {
+                self.events.error("audio device", format!("{e}; falling back to default"));
+                match RodioBackend::open_any() {
+                    Ok(backend) => backend,
+                    Err(e) => { self.events.error("audio OutputStream", e); return; }
+                }
+            }
+        };
+        if self.attach_output(Box::new(backend)) {
+            self.recovery = None;
+        }
+    }
+
+    // Play position of the current BGM; zero when nothing is playing. The sink counts total
+    // play time across loops, so it's wrapped by the track length when the format reports one.
+    fn bgm_position(&mut self) -> Duration {
+        let (Some(sink), Some(src)) = (&self.bgm, &self.bgm_source) else { return Duration::ZERO };
+        let pos = sink.get_pos();
+        let length = *self.bgm_lengths.entry(format!("{src:?}")).or_insert_with(|| bgm_source_length(src));
+        match length {
+            Some(len) if !len.is_zero() => Duration::from_nanos((pos.as_nanos() % len.as_nanos()) as u64),
+            _ => pos,
+        }
+    }
+
+    fn state(&mut self) -> AudioState {
+        self.active_sfx.retain(|_, v| !v.finished());
+        let paused = self.bgm.as_ref().is_some_and(|s| s.is_paused());
+        AudioState {
+            master_volume: self.buses.master,
+            sfx_volume: self.buses.sfx,
+            bgm_volume: self.buses.bgm,
+            muted: self.muted,
+            bgm_track: self.bgm_source.as_ref().map(BgmSource::label),
+            bgm_playing: self.bgm.is_some() && !paused,
+            bgm_paused: paused,
+            active_sfx: self.active_sfx.len(),
+            dropped_sfx: SFX_DROPPED.load(Ordering::Relaxed),
+            device: self.device_name.clone(),
+            sample_rate: self.output_rate,
+        }
+    }
+
+    // `src` names the embedded track that's playing now (loop points aside).
+    fn is_current_track(&self, src: &BgmSource) -> bool {
+        let (Some(BgmSource::Track { name: current, .. }), BgmSource::Track { name, .. }) = (&self.bgm_source, src) else {
+            return false;
+        };
+        self.bgm.is_some() && current == name
+    }
+
+    fn stop_bgm_now(&mut self) {
+        if let Some(s) = self.bgm.take() {
+            s.stop();
+        }
+        self.bgm_source = None;
+        self.bgm_fade = None;
+        for (s, ..) in self.bgm_outgoing.drain(..) {
+            s.stop();
+        }
+    }
+
+    // Silence everything before the thread exits (the OutputStream is dropped after this).
+    fn shutdown(&mut self) {
+        self.stop_bgm_now();
+        if let Some(s) = self.boost.take() {
+            s.stop();
+        }
+        if let Some((s, _)) = self.boost_outgoing.take() {
+            s.stop();
+        }
+        self.stop_ambient();
+        if let Some((s, ..)) = self.bgm_audition.take() {
+            s.stop();
+        }
+        if let Some(s) = self.preview.take() {
+            s.stop();
+        }
+        for (_, v) in self.active_sfx.drain() {
+            v.stop();
+        }
+        self.sfx_pool.stop_all();
+    }
+
+    fn boost_gain(&self) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        self.buses.sfx_gain(BOOST_VOL_MIN + (BOOST_VOL_MAX - BOOST_VOL_MIN) * self.boost_speed)
+            * self.sfx_duck_level(Instant::now())
+    }
+
+    // Requested SFX level (volume x trim) capped at the configured headroom. Commands only
+    // floor volumes at 0, so this is the one place the ceiling is applied.
+    fn sfx_level(&self, level: f32) -> f32 {
+        if level.is_nan() { 0.0 } else { level.clamp(0.0, self.tuning.sfx_max_gain) }
+    }
+
+    fn set_sfx_duck(&mut self, amount: f32) {
+        let now = Instant::now();
+        self.sfx_duck_fade = Some(Ramp::new(self.sfx_duck_level(now), amount, SFX_DUCK_RAMP));
+        self.sfx_duck = amount;
+        self.apply_sfx_volume(now);
+    }
+
+    // Current menu duck, partway through its ramp if it's changing.
+    fn sfx_duck_level(&self, now: Instant) -> f32 {
+        self.sfx_duck_fade.map(|r| r.value(now)).unwrap_or(self.sfx_duck)
+    }
+
+    // Pool, preview and boost volumes from the menu duck and the quit fade. "ui" is never
+    // ducked so menu clicks stay crisp; the preview isn't either (it's the sound test).
+    fn apply_sfx_volume(&self, now: Instant) {
+        let quit = self.quit_fade.map(|r| r.value(now)).unwrap_or(1.0);
+        self.sfx_pool.set_volume(quit, quit * self.sfx_duck_level(now));
+        if let Some(s) = &self.preview {
+            s.set_volume(quit);
+        }
+        if let Some(s) = &self.boost {
+            s.set_volume(self.boost_gain());
+        }
+    }
+
+    fn apply_boost(&self) {
+        if let Some(s) = &self.boost {
+            s.set_speed(BOOST_PITCH_MIN + (BOOST_PITCH_MAX - BOOST_PITCH_MIN) * self.boost_speed);
+            s.set_volume(self.boost_gain());
+        }
+    }
+
+    fn start_boost(&mut self) {
+        if self.boost.is_some() {
+            return;
+        }
+        let sink = match self.backend.new_sink() {
+            Ok(s) => s,
+            Err(e) => { self.sink_error("boost Sink", e); return; }
+        };
+        sink.append(LevelTap::new(boost_loop_clip(self.output_rate).into_source().repeat_infinite(), self.sfx_meter.clone()));
+        self.boost = Some(sink);
+        self.apply_boost();
+    }
+
+    fn ambient_level(&self, now: Instant) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        let quit = self.quit_fade.map(|r| r.value(now)).unwrap_or(1.0);
+        self.buses.ambient_gain(self.ambient_vol) * quit
+    }
+
+    fn apply_ambient_volume(&self, now: Instant) {
+        if let Some((s, _)) = &self.ambient {
+            s.set_volume(self.ambient_level(now));
+        }
+    }
+
+    // Decoded up front and looped like embedded BGM, but never low-passed or metered.
+    fn start_ambient(&mut self, track: String) {
+        self.stop_ambient();
+        let Some(bytes) = bgm_track_bytes(&track) else { return };
+        let clip = match decode_reader(Cursor::new(bytes)) {
+            Ok(c) => Arc::new(c),
+            Err(e) => { self.decode_error("ambient Decoder", e); return; }
+        };
+        let Some(looped) = LoopedTrack::whole(clip) else {
+            self.decode_error("ambient Decoder", "track is empty");
+            return;
+        };
+        let sink = match self.backend.new_sink() {
+            Ok(s) => s,
+            Err(e) => { self.sink_error("ambient Sink", e); return; }
+        };
+        sink.set_volume(self.ambient_level(Instant::now()));
+        sink.append(MonoMix::new(looped));
+        self.ambient = Some((sink, track));
+    }
+
+    fn stop_ambient(&mut self) {
+        if let Some((s, _)) = self.ambient.take() {
+            s.stop();
+        }
+    }
+
+    // Fade the loop out rather than cutting it mid-cycle.
+    fn stop_boost(&mut self) {
+        let Some(sink) = self.boost.take() else { return };
+        if let Some((old, _)) = self.boost_outgoing.take() {
+            old.stop();
+        }
+        let ramp = Ramp::new(sink.volume(), 0.0, BOOST_RELEASE);
+        self.boost_outgoing = Some((sink, ramp));
+    }
+
+    fn crossfade_bgm(&mut self, src: BgmSource, volume: f32, duration: Duration) {
+        self.beat_switch = None;
+        let now = Instant::now();
+        let Some(sink) = self.open_bgm(&src) else { return };
+
+        // Whatever is playing (possibly itself still fading in) fades down from its current level.
+        if let Some(old) = self.bgm.take() {
+            let level = self.bgm_fade.map(|f| f.value(now)).unwrap_or(1.0);
+            self.bgm_outgoing.push((old, self.bgm_base_gain(), Ramp::new(level, 0.0, duration)));
+        }
+
+        self.bgm_vol = volume;
+        self.bgm = Some(sink);
+        self.bgm_start_pending = Some(src.label());
+        self.bgm_source = Some(src);
+        self.bgm_fade = Some(Ramp::new(0.0, 1.0, duration));
+        self.apply_bgm_volume(now);
+    }
+
+    fn handle(&mut self, msg: AudioMsg) {
+        match msg {
+            AudioMsg::Sfx { id, kind, volume, pitch, pitch_range, pan, variant, echo, sample, distance } => {
+                self.play_sfx(id, kind, volume, pitch, pitch_range, pan, variant, echo, sample, distance);
+            }
+            AudioMsg::SfxBatch { shots } => {
+                for (id, ev) in shots {
+                    let pitch = ev.pitch.unwrap_or(1.0);
+                    let pan = ev.pan.unwrap_or(0.0);
+                    self.play_sfx(id, ev.kind, ev.volume, pitch, None, pan, 0, None, None, 0.0);
+                }
+            }
+            AudioMsg::SfxFile { .. } if self.muted => {}
+            AudioMsg::SfxFile { id, clip, volume } => {
+                let amp = self.buses.sfx_gain(self.sfx_level(volume));
+                self.backend.sfx_played("file", amp, 0.0);
+                self.enqueue_sfx(id, "file", clip.into_source().amplify(amp));
+            }
+            AudioMsg::Synth { .. } if self.muted => {}
+            AudioMsg::Synth { id, spec, volume } => {
+                let amp = self.buses.sfx_gain(self.sfx_level(volume));
+                self.backend.sfx_played("synth", amp, 0.0);
+                self.enqueue_sfx(id, "synth", spec.to_buffer(self.output_rate).amplify(amp));
+            }
+            AudioMsg::SfxStop { id } => {
+                if let Some(v) = self.active_sfx.remove(&id) {
+                    v.stop();
+                }
+                self.scheduled_sfx.retain(|(_, pending, ..)| *pending != id);
+            }
+            AudioMsg::SfxCooldown { kind, ms } => {
+                self.sfx_cooldowns.insert(kind, ms);
+            }
+            AudioMsg::SfxStopAll => {
+                // Stop flags rather than Sink::stop, so the pool sinks stay usable.
+                for (_, v) in self.active_sfx.drain() {
+                    v.stop();
+                }
+                if let Some(s) = self.preview.take() {
+                    s.stop();
+                }
+                if let Some(s) = self.boost.take() {
+                    s.stop();
+                }
+                if let Some((s, _)) = self.boost_outgoing.take() {
+                    s.stop();
+                }
+            }
+            AudioMsg::BgmPlay { track, volume, fade, loop_points, looping } => {
+                let volume = volume.unwrap_or(self.bgm_vol);
+                self.beat_switch = None;
+                let src = BgmSource::Track { name: track, loop_points, looping };
+                if self.is_current_track(&src) {
+                    self.bgm_vol = volume;
+                    self.apply_bgm_volume(Instant::now());
+                } else if self.bgm.is_some() && !fade.is_zero() {
+                    self.crossfade_bgm(src, volume, fade);
+                } else {
+                    self.stop_bgm_now();
+                    self.bgm_vol = volume;
+                    self.start_bgm(src);
+                    if !fade.is_zero() {
+                        self.bgm_fade = Some(Ramp::new(0.0, 1.0, fade));
+                    }
+                    self.apply_bgm_volume(Instant::now());
+                }
+            }
+            AudioMsg::BgmCrossfade { track, volume, duration } => {
+                self.crossfade_bgm(BgmSource::Track { name: track, loop_points: None, looping: true }, volume, duration);
+            }
+            AudioMsg::BgmSwitchOnBeat { track, bpm, fade } => {
+                let playing = self.bgm.as_ref().is_some_and(|s| !s.is_paused());
+                if playing {
+                    let at = Instant::now() + until_next_beat(self.bgm_position(), bpm);
+                    self.beat_switch = Some((at, track, fade));
+                } else {
+                    let src = BgmSource::Track { name: track, loop_points: None, looping: true };
+                    self.crossfade_bgm(src, self.bgm_vol, fade);
+                }
+            }
+            AudioMsg::BgmVolume { volume } => {
+                self.bgm_vol = volume;
+                self.apply_bgm_volume(Instant::now());
+            }
+            // Sent on every slider update, so only an actual change does anything.
+            AudioMsg::BgmMuted { muted } if muted == self.bgm_muted => {}
+            AudioMsg::BgmMuted { muted } => {
+                self.bgm_muted = muted;
+                self.apply_bgm_volume(Instant::now());
+            }
+            AudioMsg::Tuning { tuning } => self.tuning = tuning,
+            AudioMsg::Shutdown => self.shutdown(),
+            AudioMsg::SetDevice { name } => self.set_device(&name),
+            AudioMsg::BgmSpeed { factor } => {
+                // Sink::set_speed resamples the live source in place, so no rebuild (and no
+                // position jump) is needed.
+                self.bgm_speed = factor;
+                if let Some(s) = &self.bgm {
+                    s.set_speed(self.bgm_speed_now(Instant::now()));
+                }
+            }
+            AudioMsg::BgmSeek { pos, reply } => {
+                let res = match &self.bgm {
+                    Some(s) => s.try_seek(pos).map_err(|e| match e {
+                        SeekError::NotSupported { .. } => AppError::Unsupported(format!("bgm seek: {e}")),
+                        _ => AppError::Decode(format!("bgm seek: {e}")),
+                    }),
+                    None => Ok(()),
+                };
+                let _ = reply.send(res);
+            }
+            AudioMsg::Lowpass { filter } => self.set_lowpass(filter),
+            AudioMsg::QueryState { reply } => {
+                let _ = reply.send(self.state());
+            }
+            AudioMsg::QueryStats { reply } => {
+                let _ = reply.send(AudioStats::now());
+            }
+            AudioMsg::QueryBgm { reply } => {
+                let _ = reply.send(self.bgm.as_ref().is_some_and(|s| !s.is_paused()));
+            }
+            AudioMsg::QueryBgmPosition { reply } => {
+                let _ = reply.send(self.bgm_position());
+            }
+            AudioMsg::PerceptualVolume { enabled } => {
+                self.buses.perceptual = enabled;
+                self.apply_bgm_volume(Instant::now());
+                self.apply_boost();
+            }
+            AudioMsg::SetMuted { muted } => {
+                self.muted = muted;
+                self.apply_bgm_volume(Instant::now());
+                self.apply_ambient_volume(Instant::now());
+                self.apply_boost();
+            }
+            AudioMsg::SfxLimiter { enabled } => self.sfx_limiter = enabled,
+            AudioMsg::SfxDuck { amount } => self.set_sfx_duck(amount),
+            AudioMsg::EnterPause => self.enter_menu_pause(),
+            AudioMsg::ExitPause => self.exit_menu_pause(),
+            AudioMsg::ScheduleSfx { id, kind, at, volume } => {
+                if self.scheduled_sfx.len() < SCHEDULED_SFX_MAX {
+                    self.scheduled_sfx.push((at, id, kind, volume));
+                } else {
+                    SFX_DROPPED.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            AudioMsg::CancelScheduledSfx => self.scheduled_sfx.clear(),
+            // Stale reports (from before a switch or an earlier loss) are ignored.
+            AudioMsg::DeviceLost { name } => {
+                if self.recovery.is_none() && self.device_name.as_deref() == Some(name.as_str()) {
+                    self.device_lost(Instant::now(), "device disappeared");
+                }
+            }
+            AudioMsg::PreviewSfx { kind, volume } => self.preview_sfx(&kind, volume),
+            AudioMsg::TestTone { freq, duration, volume } => self.play_test_tone(freq, duration, volume),
+            AudioMsg::Warmup => self.sfx_pool.warm_up(self.output_rate),
+            AudioMsg::BoostLoopStart => self.start_boost(),
+            AudioMsg::BoostLoopSet { speed } => {
+                self.boost_speed = speed;
+                self.apply_boost();
+            }
+            AudioMsg::BoostLoopStop => self.stop_boost(),
+            AudioMsg::BgmPreview { track, volume } => self.start_audition(track, volume),
+            AudioMsg::BgmPreviewStop => self.stop_audition(),
+            AudioMsg::AmbientPlay { track, volume } => {
+                self.ambient_vol = volume;
+                if self.ambient.as_ref().is_some_and(|(_, current)| *current == track) {
+                    self.apply_ambient_volume(Instant::now());
+                } else {
+                    self.start_ambient(track);
+                }
+            }
+            AudioMsg::AmbientStop => self.stop_ambient(),
+            AudioMsg::SetBus { bus, volume } => {
+                match bus {
+                    Bus::Master => self.buses.master = volume,
+                    Bus::Sfx => self.buses.sfx = volume,
+                    Bus::Bgm => self.buses.bgm = volume,
+                }
+                // SFX pick the new gain up on their next play; the live BGM, ambient and boost
+                // sinks need a rescale now.
+                self.apply_bgm_volume(Instant::now());
+                self.apply_ambient_volume(Instant::now());
+                self.apply_boost();
+            }
+            AudioMsg::ApplyConfig { config } => self.apply_mixer_config(config),
+            AudioMsg::BgmPlayFile { path, volume } => {
+                self.play_bgm_file(path, volume);
+            }
+            AudioMsg::BgmPause => {
+                self.blur_paused = false;
+                self.bgm_held = false;
+                if let Some(s) = &self.bgm {
+                    s.pause();
+                }
+            }
+            AudioMsg::BgmResume => {
+                self.blur_paused = false;
+                self.bgm_held = false;
+                if let Some(s) = &self.bgm {
+                    s.play();
+                }
+            }
+            AudioMsg::WindowFocus { focused: false } => {
+                if let Some(s) = self.bgm.as_ref().filter(|s| !s.is_paused()) {
+                    s.pause();
+                    self.blur_paused = true;
+                }
+            }
+            AudioMsg::WindowFocus { focused: true } => {
+                // Mid-preview, the main BGM stays paused until the preview ends.
+                if self.bgm_audition.is_some() && self.blur_paused {
+                    self.blur_paused = false;
+                    self.bgm_held = true;
+                } else if std::mem::take(&mut self.blur_paused) {
+                    if let Some(s) = &self.bgm {
+                        s.play();
+                    }
+                }
+            }
+            AudioMsg::BgmStop { fade } => self.stop_bgm(fade),
+            AudioMsg::FadeOutAll { fade } => {
+                self.stop_bgm(fade);
+                self.stop_boost();
+                self.quit_fade = Some(Ramp::new(1.0, 0.0, fade));
+            }
+        }
+    }
+
+    // Everything from an `AudioConfig` first, then one rescale of the live sinks.
+    fn apply_mixer_config(&mut self, config: AudioConfig) {
+        let bus = |v: f32| if v.is_finite() { v.clamp(0.0, 1.0) } else { 1.0 };
+        self.buses.master = bus(config.master_volume);
+        self.buses.sfx = bus(config.sfx_volume);
+        self.buses.bgm = bus(config.bgm_volume);
+        self.muted = config.muted;
+        if let Some(name) = config.device {
+            let wanted = name.strip_suffix(DEFAULT_DEVICE_MARKER).unwrap_or(&name);
+            if self.device_name.as_deref() != Some(wanted) {
+                self.set_device(&name);
+            }
+        }
+        let now = Instant::now();
+        self.apply_bgm_volume(now);
+        self.apply_sfx_volume(now);
+        self.apply_ambient_volume(now);
+        self.apply_boost();
+    }
+
+    fn stop_bgm(&mut self, fade: Duration) {
+        self.beat_switch = None;
+        if fade.is_zero() {
+            self.stop_bgm_now();
+        } else if let Some(s) = self.bgm.take() {
+            // Ramp down from the current level; tick() stops the sink once it hits 0.
+            let level = self.bgm_envelope(Instant::now());
+            let base = self.bgm_base_gain();
+            self.bgm_source = None;
+            self.bgm_fade = None;
+            self.bgm_outgoing.push((s, base, Ramp::new(level, 0.0, fade)));
+        }
+    }
+
+    // A `FadeOutAll` has run its course: the thread can stop everything and exit.
+    fn quit_faded(&self, now: Instant) -> bool {
+        self.quit_fade.is_some_and(|r| r.done(now)) && self.bgm_outgoing.is_empty() && self.boost_outgoing.is_none()
+    }
+
+    // Hand a ready-to-play source to the pool and track it under `id` for `stop_sfx`.
+    fn enqueue_sfx<S>(&mut self, id: u64, kind: &str, src: S)
+    where
+        S: Source<Item = f32> + Send + 'static,
+    {
+        // Drop finished voices so the map only ever holds what's actually playing.
+        self.active_sfx.retain(|_, v| !v.finished());
+
+        let (voice, src) = SfxVoice::wrap(SoftLimit { inner: src, enabled: self.sfx_limiter });
+        let cutoff = self.lowpass.filter(|f| f.sfx).map(|f| f.cutoff_hz);
+        append_filtered(self.sfx_pool.pick(kind), src, cutoff, &self.sfx_meter);
+        self.active_sfx.insert(id, voice);
+    }
+
+    fn preview_sfx(&mut self, kind: &str, volume: f32) {
+        let src = if kind == "enemy_pickup" {
+            enemy_pickup_variant(0, self.output_rate).into_source()
+        } else {
+            if !self.sfx_cache.contains_key(kind) {
+                let clips = decode_sfx_kind(kind, &self.events);
+                if clips.is_empty() {
+                    return;
+                }
+                self.sfx_cache.insert(kind.to_string(), clips);
+            }
+            self.sfx_cache[kind][0].to_source()
+        };
+        // A fresh sink each time; dropping the old one stops its sound.
+        let sink = match self.backend.new_sink() {
+            Ok(s) => s,
+            Err(e) => return self.sink_error("preview Sink", e),
+        };
+        let amp = self.buses.sfx_gain(self.sfx_level(volume * sfx_trim(kind)));
+        append_filtered(&sink, src.amplify(amp), None, &self.sfx_meter);
+        self.preview = Some(sink);
+    }
+
+    // Replaces any preview, like `preview_sfx`.
+    fn play_test_tone(&mut self, freq: f32, duration: Duration, volume: f32) {
+        let clip = synth::test_tone(freq, duration.as_secs_f32() * 1000.0)
+            .render(self.output_rate)
+            .with_edge_fades(TEST_TONE_FADE);
+        let sink = match self.backend.new_sink() {
+            Ok(s) => s,
+            Err(e) => return self.sink_error("preview Sink", e),
+        };
+        append_filtered(&sink, clip.into_source().amplify(volume), None, &self.sfx_meter);
+        self.preview = Some(sink);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn play_sfx(
+        &mut self,
+        id: u64,
+        kind: String,
+        volume: f32,
+        pitch: f32,
+        pitch_range: Option<(f32, f32)>,
+        pan: f32,
+        variant: u32,
+        echo: Option<Echo>,
+        sample: Option<u32>,
+        distance: f32,
+    ) {
+        if self.muted {
+            return;
+        }
+        let now = Instant::now();
+        // "ui" skips the cooldown (even a config.toml one) and the sfx bus: master and mute only.
+        let ui = kind == "ui";
+        if let Some(last) = self.sfx_last_played.get(&kind).filter(|_| !ui) {
+            if now.duration_since(*last) < sfx_cooldown(&self.tuning, &self.sfx_cooldowns, &kind) {
+                return;
+            }
+        }
+        self.sfx_last_played.insert(kind.clone(), now);
+
+        // Same volume behavior for you + rival (rivals then fall off with distance).
+        let level = self.sfx_level(volume * sfx_trim(&kind));
+        let mut amp = if ui { self.buses.ui_gain(level) } else { self.buses.sfx_gain(level) };
+        let pitch = match pitch_range {
+            Some((lo, hi)) => self.rng.range(lo.min(hi), lo.max(hi)),
+            None => pitch,
+        };
+        // clamp() passes NaN through, so fall back to unpitched explicitly.
+        let pitch = if pitch.is_finite() { pitch.clamp(SFX_PITCH_MIN, SFX_PITCH_MAX) } else { 1.0 };
+        let pan = if pan.is_finite() { pan.clamp(-1.0, 1.0) } else { 0.0 };
+
+        let generated;
+        let clip = if kind == "enemy_pickup" {
+            amp *= distance_gain(distance);
+            generated = enemy_pickup_variant(variant, self.output_rate);
+            &generated
+        } else {
+            if !self.sfx_cache.contains_key(&kind) {
+                let clips = decode_sfx_kind(&kind, &self.events);
+                if clips.is_empty() {
+                    return;
+                }
+                self.sfx_cache.insert(kind.clone(), clips);
+            }
+
+            let count = self.sfx_cache.get(&kind).map_or(0, Vec::len);
+            if count == 0 {
+                return;
+            }
+            let idx = match sample {
+                Some(i) => i as usize % count,
+                None => (self.rng.next_u64() % count as u64) as usize,
+            };
+            &self.sfx_cache[&kind][idx]
+        };
+        let src = match echo {
+            Some(e) => clip.with_echo(e).panned(pan),
+            None => clip.panned(pan),
+        };
+
+        self.backend.sfx_played(&kind, amp, pan);
+        self.enqueue_sfx(id, &kind, src.speed(pitch).amplify(amp));
+
+        if DUCKING_SFX.contains(&kind.as_str()) {
+            let release = Duration::from_millis(self.tuning.duck_release_ms);
+            self.duck = Some(Ramp::new(self.tuning.duck_level, 1.0, release));
+            self.apply_bgm_volume(now);
+        }
+    }
+}
+
+// A dragged slider queues a burst of BgmVolume/BgmMuted pairs. Collapse `first` and every
+// such message queued right behind it into the newest volume and mute, in that order; the
+// first other message taken off the queue is returned separately, to handle next.
+fn coalesce_bgm_volume(first: AudioMsg, rx: &Receiver<AudioMsg>) -> (Vec<AudioMsg>, Option<AudioMsg>) {
+    let (mut volume, mut muted) = (None, None);
+    let mut held = None;
+    let mut next = Some(first);
+    while let Some(msg) = next.take() {
+        match msg {
+            AudioMsg::BgmVolume { volume: v } => volume = Some(v),
+            AudioMsg::BgmMuted { muted: m } => muted = Some(m),
+            other => {
+                held = Some(other);
+                break;
+            }
+        }
+        next = rx.try_recv().ok();
+    }
+    let msgs = volume
+        .map(|volume| AudioMsg::BgmVolume { volume })
+        .into_iter()
+        .chain(muted.map(|muted| AudioMsg::BgmMuted { muted }))
+        .collect();
+    (msgs, held)
+}
+
+// Next message from the control or SFX queue: Ok(None) on timeout, Err once the app side has
+// gone away.
+fn recv_audio(rx: &Receiver<AudioMsg>, sfx_rx: &Receiver<AudioMsg>, timeout: Option<Duration>) -> Result<Option<AudioMsg>, ()> {
+    let mut sel = Select::new();
+    let control = sel.recv(rx);
+    sel.recv(sfx_rx);
+    let op = match timeout {
+        Some(t) => match sel.select_timeout(t) {
+            Ok(op) => op,
+            Err(_) => return Ok(None),
+        },
+        None => sel.select(),
+    };
+    let from = if op.index() == control { rx } else { sfx_rx };
+    op.recv(from).map(Some).map_err(|_| ())
+}
+
+fn audio_thread(rx: Receiver<AudioMsg>, sfx_rx: Receiver<AudioMsg>, events: AudioEvents) {
+    let _alive = AudioAlive;
+    let backend = match RodioBackend::open_any() {
+        Ok(v) => v,
+        Err(e) => {
+            // Dropping `_alive` marks audio unavailable, so commands report audio_shut_down.
+            events.error("audio OutputStream", e);
+            return;
+        }
+    };
+
+    let mut engine = match AudioEngine::new(Box::new(backend), events.clone()) {
+        Ok(e) => e,
+        Err(e) => {
+            SINK_FAILURES.fetch_add(1, Ordering::Relaxed);
+            events.error("sfx Sink", e);
+            return;
+        }
+    };
+    set_watched_device(engine.device_name.clone());
+    // The stream starts lazily, which made the first SFX after launch noticeably late.
+    engine.handle(AudioMsg::Warmup);
+
+    // A message taken off the queue while coalescing, handled before waiting for more.
+    let mut held = None;
+    loop {
+        // Block until the next message while idle; wake up every AUDIO_TICK while ramping
+        // (e.g. to restore BGM after a duck), when a device recovery retry is due and for each
+        // "audio-level" reading while something plays, even if nothing new arrives.
+        let msg = match held.take() {
+            Some(m) => Some(m),
+            None => {
+                let Ok(msg) = recv_audio(&rx, &sfx_rx, engine.wake_after(Instant::now())) else { break };
+                msg
+            }
+        };
+
+        if let Some(msg) = msg {
+            if matches!(msg, AudioMsg::BgmVolume { .. } | AudioMsg::BgmMuted { .. }) {
+                let (msgs, next) = coalesce_bgm_volume(msg, &rx);
+                for m in msgs {
+                    engine.handle(m);
+                }
+                held = next;
+            } else {
+                let shutdown = matches!(msg, AudioMsg::Shutdown);
+                engine.handle(msg);
+                if shutdown {
+                    break;
+                }
+            }
+        }
+        let now = Instant::now();
+        engine.tick(now);
+        if engine.quit_faded(now) {
+            engine.shutdown();
+            break;
+        }
+    }
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    let (tx, rx) = unbounded::<AudioMsg>();
+    let (sfx_tx, sfx_rx) = bounded::<AudioMsg>(SFX_QUEUE_CAP);
+    let settings_tx = tx.clone();
+    let audio_exit = tx.clone();
+    let audio_focus = tx.clone();
+    let (log_tx, log_rx) = unbounded::<LogMsg>();
+    let log_thread_handle = LogThread::default();
+    let log_join = log_thread_handle.clone();
+    let log_exit = log_tx.clone();
+    let log_start = log_tx.clone();
+    install_panic_hook();
+
+    tauri::Builder::default()
+        .plugin(tauri_plugin_opener::init())
+        .manage(AudioTx(tx))
+        .manage(SfxTx(sfx_tx))
+        .manage(LogTx(log_tx))
+        .manage(ReplayState::default())
+        .manage(StatsState::default())
+        .manage(PauseOnBlur::default())
+        .setup(move |app| {
+            // Log writer: owns the open log file (in the temp dir if the app data dir can't be
+            // used).
+            let settings = read_settings(app.handle());
+            let (path, warning) = open_session_log(app.handle(), settings.log_mode);
+            let thread = std::thread::spawn(move || log_thread(log_rx, path));
+            let version = app.package_info().version.to_string();
+            let mut first = vec![session_marker("start", &version)];
+            first.extend(warning.map(|w| format_log_line(LogLevel::Warn, &w)));
+            let _ = log_start.send(LogMsg::Lines(first));
+            if let Ok(mut h) = log_thread_handle.lock() {
+                *h = Some(thread);
+            }
+
+            // Audio thread: owns OutputStream so we avoid Send/Sync issues. Started here so it
+            // can report failures to the frontend through the app handle.
+            // Queued ahead of anything else, so the first sounds already use the saved prefs.
+            let config = read_config(app.handle()).unwrap_or_else(|e| {
+                eprintln!("config: {e}; using defaults");
+                None
+            });
+            let config = config.unwrap_or_default();
+            let (wake_tx, wake_rx) = unbounded::<()>();
+            let music = Arc::new(MusicVolume::new(app_data_file(app.handle(), MUSIC_VOLUME_FILE_NAME).ok(), wake_tx));
+            apply_config(&settings_tx, &config);
+            let _ = settings_tx.send(AudioMsg::BgmVolume { volume: startup_music_volume(music.saved(), &config) });
+            apply_settings(&settings_tx, &settings);
+            app.state::<PauseOnBlur>().0.store(settings.pause_on_blur, Ordering::Relaxed);
+
+            let events = AudioEvents(Some(app.handle().clone()));
+            let check = events.clone();
+            let thread = std::thread::spawn(move || audio_thread(rx, sfx_rx, events));
+            let watch_tx = settings_tx.clone();
+            std::thread::spawn(move || device_watch_thread(watch_tx));
+            // Asset check off the audio thread so it doesn't hold up the first sounds. The
+            // result goes out as "audio-assets" ([name, ok] pairs).
+            std::thread::spawn(move || check.emit("audio-assets", verify_audio_assets()));
+            app.manage(AudioThread(Mutex::new(Some(thread))));
+            let writer = music.clone();
+            std::thread::spawn(move || music_volume_thread(writer, wake_rx));
+            app.manage(music);
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            app_info,
+            append_log,
+            log_event,
+            log_kv,
+            log_json,
+            session_id,
+            log_path,
+            read_log_tail,
+            clear_log,
+            export_logs,
+            save_high_score,
+            load_high_scores,
+            save_settings,
+            load_settings,
+            replay_start,
+            replay_record,
+            replay_save,
+            replay_load,
+            replay_list,
+            capture_screenshot,
+            set_fullscreen,
+            set_always_on_top,
+            reload_config,
+            record_stat,
+            load_stats,
+            play_sfx,
+            play_sfx_batch,
+            list_sfx_kinds,
+            preview_sfx,
+            play_test_tone,
+            audio_warmup,
+            stop_sfx,
+            stop_all_sfx,
+            schedule_sfx,
+            cancel_scheduled_sfx,
+            set_sfx_cooldown,
+            play_sfx_file,
+            play_synth,
+            bgm_play,
+            bgm_play_file,
+            analyze_loudness,
+            bgm_crossfade,
+            bgm_switch_on_beat,
+            bgm_stop,
+            bgm_preview,
+            bgm_preview_stop,
+            bgm_pause,
+            bgm_resume,
+            set_pause_on_blur,
+            set_mono_output,
+            bgm_position_ms,
+            bgm_is_playing,
+            audio_state,
+            audio_stats,
+            bgm_seek,
+            bgm_speed,
+            set_lowpass,
+            set_muted,
+            set_perceptual_volume,
+            boost_loop_start,
+            boost_loop_set,
+            boost_loop_stop,
+            ambient_play,
+            ambient_stop,
+            set_sfx_limiter,
+            set_sfx_duck,
+            audio_enter_pause,
+            audio_exit_pause,
+            verify_audio,
+            bgm_volume,
+            set_bus_volume,
+            apply_audio_config,
+            audio_shutdown,
+            list_audio_devices,
+            set_audio_device,
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(move |app, event| {
+            // Only a blur needs the setting; a focus always goes through so music paused before
+            // the setting was switched off still comes back.
+            if let tauri::RunEvent::WindowEvent { event: tauri::WindowEvent::Focused(focused), .. } = event {
+                let enabled = app.state::<PauseOnBlur>().0.load(Ordering::Relaxed);
+                if focused || enabled {
+                    let _ = audio_focus.send(AudioMsg::WindowFocus { focused });
+                }
+                return;
+            }
+            if let tauri::RunEvent::Exit = event {
+                if let Some(music) = app.try_state::<Arc<MusicVolume>>() {
+                    music.flush();
+                }
+                // Fade the mix out instead of cutting it, but don't hold up quitting past the fade.
+                if audio_exit.send(AudioMsg::FadeOutAll { fade: QUIT_FADE }).is_ok() {
+                    let thread = app.try_state::<AudioThread>().and_then(|t| t.0.lock().ok()?.take());
+                    if let Some(h) = thread {
+                        let deadline = Instant::now() + QUIT_FADE + QUIT_FADE_SLACK;
+                        while !h.is_finished() && Instant::now() < deadline {
+                            std::thread::sleep(Duration::from_millis(5));
+                        }
+                    }
+                }
+                // Close the session, then let the log writer flush whatever is still buffered
+                // before the process ends.
+                let version = app.package_info().version.to_string();
+                let _ = log_exit.send(LogMsg::Lines(vec![session_marker("end", &version)]));
+                let _ = log_exit.send(LogMsg::Shutdown);
+                if let Some(h) = log_join.lock().ok().and_then(|mut h| h.take()) {
+                    let _ = h.join();
+                }
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // (kind, gain, pan) of each queued SFX.
+    type Played = Rc<RefCell<Vec<(String, f32, f32)>>>;
+
+    // Sinks that nobody pulls from, plus a log of every SFX the engine queued.
+    #[derive(Default)]
+    struct NullBackend {
+        played: Played,
+        outputs: RefCell<Vec<rodio::queue::SourcesQueueOutput<f32>>>,
+    }
+
+    impl AudioBackend for NullBackend {
+        fn new_sink(&self) -> Result<Sink, PlayError> {
+            let (sink, output) = Sink::new_idle();
+            self.outputs.borrow_mut().push(output);
+            Ok(sink)
+        }
+
+        fn device_name(&self) -> Option<String> {
+            None
+        }
+
+        fn sample_rate(&self) -> u32 {
+            FALLBACK_SAMPLE_RATE
+        }
+
+        fn sfx_played(&self, kind: &str, gain: f32, pan: f32) {
+            self.played.borrow_mut().push((kind.to_string(), gain, pan));
+        }
+    }
+
+    fn engine() -> (AudioEngine, Played) {
+        let backend = NullBackend::default();
+        let played = backend.played.clone();
+        let engine = AudioEngine::new(Box::new(backend), AudioEvents(None)).expect("engine");
+        (engine, played)
+    }
+
+    fn sfx(kind: &str, volume: f32, pan: f32) -> AudioMsg {
+        AudioMsg::Sfx {
+            id: NEXT_SFX_ID.fetch_add(1, Ordering::Relaxed),
+            kind: kind.to_string(),
+            volume,
+            pitch: 1.0,
+            pitch_range: None,
+            pan,
+            variant: 0,
+            echo: None,
+            sample: None,
+            distance: 0.0,
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn repeats_inside_the_cooldown_are_dropped() {
+        let (mut engine, played) = engine();
+        engine.handle(sfx("eat", 1.0, 0.0));
+        engine.handle(sfx("eat", 1.0, 0.0));
+        engine.handle(sfx("ui", 1.0, 0.0));
+        engine.handle(sfx("ui", 1.0, 0.0));
+        let kinds: Vec<_> = played.borrow().iter().map(|(k, _, _)| k.clone()).collect();
+        assert_eq!(kinds, ["eat", "ui", "ui"]);
+    }
+
+    #[test]
+    fn stop_right_after_play_is_handled_after_it() {
+        let (tx, rx) = unbounded();
+        let (sfx_tx, sfx_rx) = bounded(SFX_QUEUE_CAP);
+        let sfx_tx = SfxTx(sfx_tx);
+        // Repeat so a random pick between the two queues would show up.
+        for _ in 0..50 {
+            let (mut engine, played) = engine();
+            let shot = sfx("eat", 1.0, 0.0);
+            let AudioMsg::Sfx { id, .. } = shot else { unreachable!() };
+            tx.send(AudioMsg::BgmVolume { volume: 0.5 }).unwrap();
+            assert!(sfx_tx.send(shot, 1).unwrap());
+            tx.send(AudioMsg::BgmVolume { volume: 0.4 }).unwrap();
+            sfx_tx.send_ordered(AudioMsg::SfxStop { id }).unwrap();
+            while let Ok(Some(msg)) = recv_audio(&rx, &sfx_rx, Some(Duration::ZERO)) {
+                engine.handle(msg);
+            }
+            assert_eq!(played.borrow().len(), 1);
+            assert!(!engine.active_sfx.contains_key(&id));
+        }
+    }
+
+    #[test]
+    fn rapid_ui_clicks_get_their_own_sinks() {
+        let backend = NullBackend::default();
+        let mut pool = SfxPool::new(&backend).expect("pool");
+        let mut picked = Vec::new();
+        for _ in 0..UI_VOICES {
+            let sink = pool.pick("ui");
+            assert!(sink.empty());
+            sink.append(SamplesBuffer::new(1, 48_000, vec![0.0f32; 4800]));
+            picked.push(sink as *const Sink);
+        }
+        picked.sort();
+        picked.dedup();
+        assert_eq!(picked.len(), UI_VOICES);
+    }
+
+    #[test]
+    fn scheduled_sfx_fire_when_due_and_can_be_cancelled() {
+        let (mut engine, played) = engine();
+        let now = Instant::now();
+        let at = now + Duration::from_millis(500);
+        engine.handle(AudioMsg::ScheduleSfx { id: 1, kind: "eat".to_string(), at, volume: 1.0 });
+        engine.handle(AudioMsg::ScheduleSfx { id: 2, kind: "dash".to_string(), at: now + Duration::from_secs(5), volume: 1.0 });
+        assert!(engine.wake_after(now).is_some_and(|d| d <= Duration::from_millis(500)));
+
+        engine.tick(now);
+        assert!(played.borrow().is_empty());
+        engine.tick(at);
+        let kinds: Vec<_> = played.borrow().iter().map(|(k, _, _)| k.clone()).collect();
+        assert_eq!(kinds, ["eat"]);
+
+        engine.handle(AudioMsg::CancelScheduledSfx);
+        engine.tick(now + Duration::from_secs(6));
+        assert_eq!(played.borrow().len(), 1);
+    }
+
+    #[test]
+    fn bus_volumes_multiply_into_the_sfx_gain() {
+        let (mut engine, played) = engine();
+        engine.handle(AudioMsg::PerceptualVolume { enabled: false });
+        engine.handle(AudioMsg::SetBus { bus: Bus::Master, volume: 0.5 });
+        engine.handle(AudioMsg::SetBus { bus: Bus::Sfx, volume: 0.8 });
+        engine.handle(sfx("shield", 0.5, -0.25));
+        let (kind, gain, pan) = played.borrow()[0].clone();
+        assert_eq!(kind, "shield");
+        assert!(close(gain, 0.5 * 0.8 * 0.5), "gain {gain}");
+        assert!(close(pan, -0.25));
+    }
+
+    #[test]
+    fn muted_sfx_are_not_played() {
+        let (mut engine, played) = engine();
+        engine.handle(AudioMsg::SetMuted { muted: true });
+        engine.handle(sfx("eat", 1.0, 0.0));
+        assert!(played.borrow().is_empty());
+    }
+
+    #[test]
+    fn ducking_sfx_duck_the_music() {
+        let (mut engine, _) = engine();
+        engine.handle(sfx("death", 1.0, 0.0));
+        let level = engine.bgm_envelope(Instant::now());
+        assert!(level < 0.5, "envelope {level}");
+        let release = Duration::from_millis(engine.tuning.duck_release_ms);
+        engine.tick(Instant::now() + release);
+        assert!(engine.duck.is_none());
+    }
+
+    #[test]
+    fn pause_transition_restores_the_previous_mix() {
+        let (mut engine, _) = engine();
+        engine.handle(AudioMsg::SfxDuck { amount: 0.8 });
+        engine.handle(AudioMsg::EnterPause);
+        let done = Instant::now() + PAUSE_RAMP;
+        assert!(close(engine.bgm_envelope(done), PAUSE_BGM_DUCK));
+        assert!(close(engine.bgm_speed_now(done), PAUSE_BGM_SPEED));
+        assert_eq!(engine.lowpass.map(|f| f.cutoff_hz), Some(PAUSE_LOWPASS_HZ));
+        assert!(close(engine.sfx_duck, PAUSE_SFX_DUCK));
+
+        engine.handle(AudioMsg::ExitPause);
+        let done = Instant::now() + PAUSE_RAMP;
+        assert!(close(engine.bgm_envelope(done), 1.0));
+        assert!(close(engine.bgm_speed_now(done), 1.0));
+        assert_eq!(engine.lowpass, None);
+        assert!(close(engine.sfx_duck, 0.8));
+    }
+
+    #[test]
+    fn embedded_sfx_decode() {
+        for kind in SFX_KINDS {
+            let samples = decode_sfx(kind, 1.0).unwrap_or_default();
+            assert!(!samples.is_empty(), "{kind} decoded to nothing");
+        }
+        assert!(decode_sfx("nope", 1.0).is_none());
+    }
+
+    // Minimal PCM WAV: `bits`-bit little-endian integer frames.
+    fn wav(channels: u16, rate: u32, bits: u16, samples: &[i32]) -> Vec<u8> {
+        let width = bits as usize / 8;
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()[..width].to_vec()).collect();
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&channels.to_le_bytes());
+        out.extend_from_slice(&rate.to_le_bytes());
+        out.extend_from_slice(&(rate * (channels as u32) * width as u32).to_le_bytes());
+        out.extend_from_slice(&(channels * bits / 8).to_le_bytes());
+        out.extend_from_slice(&bits.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&data);
+        out
+    }
+
+    #[test]
+    fn stereo_24bit_wav_keeps_its_channels() {
+        // Left at +half scale, right at -half scale.
+        let half = 1 << 22;
+        let frames: Vec<i32> = (0..100).flat_map(|_| [half, -half]).collect();
+        let clip = decode_reader(Cursor::new(wav(2, 44_100, 24, &frames))).unwrap().into_sfx_layout();
+        assert_eq!(clip.channels, 2);
+        assert_eq!(clip.samples.len(), 200);
+        assert!(clip.samples.chunks_exact(2).all(|f| (f[0] - 0.5).abs() < 1e-3 && (f[1] + 0.5).abs() < 1e-3));
+
+        // Hard left keeps only the left channel.
+        let left: Vec<f32> = clip.panned(-1.0).collect();
+        assert!(left.chunks_exact(2).all(|f| f[0] > 0.5 && f[1].abs() < 1e-6));
+    }
+
+    #[test]
+    fn surround_sfx_downmix_to_mono() {
+        let clip = AudioClip { channels: 4, sample_rate: 44_100, samples: vec![1.0, 0.0, 0.5, 0.5, 0.2, 0.2, 0.2, 0.2, 9.0] };
+        let clip = clip.into_sfx_layout();
+        assert_eq!(clip.channels, 1);
+        assert_eq!(clip.samples.len(), 2);
+        assert!((clip.samples[0] - 0.5).abs() < 1e-6 && (clip.samples[1] - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sanitize_gain_rejects_non_finite_and_negative() {
+        assert_eq!(sanitize_gain(f32::NAN), 0.0);
+        assert_eq!(sanitize_gain(f32::INFINITY), 0.0);
+        assert_eq!(sanitize_gain(f32::NEG_INFINITY), 0.0);
+        assert_eq!(sanitize_gain(-0.5), 0.0);
+        assert_eq!(sanitize_gain(0.25), 0.25);
+        assert_eq!(sanitize_gain(1.5), 1.5);
+        assert_eq!(sanitize_gain(f32::NAN).min(1.0), 0.0);
+    }
+
+    #[test]
+    fn saved_music_volume_wins_over_the_config_default() {
+        let mut config: Config = toml::from_str("[audio]\nbgm_volume = 0.7\n").unwrap();
+        assert_eq!(startup_music_volume(None, &config), 0.7);
+        assert_eq!(startup_music_volume(Some(0.2), &config), 0.2);
+        assert_eq!(startup_music_volume(Some(3.0), &config), 1.0);
+        config.audio.default_music_volume = 5.0;
+        assert_eq!(startup_music_volume(None, &config), 1.0);
+        assert_eq!(startup_music_volume(None, &Config::default()), 0.45);
+    }
+
+    #[test]
+    fn kv_values_with_spaces_or_equals_are_quoted() {
+        assert_eq!(kv_escape("plain"), "plain");
+        assert_eq!(kv_escape(""), "\"\"");
+        assert_eq!(kv_escape("a b"), "\"a b\"");
+        assert_eq!(kv_escape("x=1"), "\"x=1\"");
+        assert_eq!(kv_escape("say \"hi\"\n"), "\"say \\\"hi\\\"\\n\"");
+
+        let fields = HashMap::from([("zone".to_string(), "top left".to_string()), ("score".to_string(), "12".to_string())]);
+        let line = format_kv_line(&fields);
+        assert!(line.ends_with(" score=12 zone=\"top left\""), "{line}");
+    }
+
+    #[test]
+    fn tail_lines_span_several_chunks() {
+        let path = std::env::temp_dir().join(format!("tail-test-{}.log", std::process::id()));
+        let text: String = (0..3000).map(|i| format!("line {i}\n")).collect();
+        std::fs::write(&path, text).unwrap();
+        let tail = read_tail_lines(&path, 2000).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(tail.len(), 2000);
+        assert_eq!(tail[0], "line 1000");
+        assert_eq!(tail[1999], "line 2999");
+    }
+
+    #[test]
+    fn amplify_scales_samples() {
+        let full = decode_sfx("eat", 1.0).unwrap();
+        let half = decode_sfx("eat", 0.5).unwrap();
+        assert_eq!(full.len(), half.len());
+        for (f, h) in full.iter().zip(&half) {
+            assert!((f * 0.5 - h).abs() < 1e-6, "{f} vs {h}");
+        }
+    }
 }
@@ -0,0 +1,156 @@
+// Building blocks for procedural SFX, plus `SynthSpec`: a sound described as data (layers
+// summed under one envelope) so the frontend can design sounds at runtime via `play_synth`.
+
+use crate::{AudioClip, Rng};
+use rodio::buffer::SamplesBuffer;
+
+const TAU: f32 = 2.0 * std::f32::consts::PI;
+// Bounds on specs from the frontend, so one call can't render minutes of audio.
+const MAX_DURATION_MS: f32 = 5000.0;
+const MAX_LAYERS: usize = 16;
+
+// Linear attack up to 1, then linear decay to 0 at `dur`, raised to `curve` (2.0 gives the
+// snappy fast-attack/quick-decay shape).
+pub(super) fn envelope(t: f32, attack: f32, dur: f32, curve: f32) -> f32 {
+    let env = if t < attack { t / attack } else { ((dur - t) / (dur - attack)).max(0.0) };
+    env.powf(curve)
+}
+
+pub(super) fn sine(freq: f32, t: f32) -> f32 {
+    (TAU * freq * t).sin()
+}
+
+// Naive saw, -1..1. Aliases at high pitches, which is fine for short effects.
+pub(super) fn saw(freq: f32, t: f32) -> f32 {
+    2.0 * (freq * t).fract() - 1.0
+}
+
+// Sweep from `f0` towards `f1` over `dur`. The phase is the current frequency times `t`
+// (so it actually overshoots `f1`), which is how the rival chirp has always sounded.
+pub(super) fn chirp(f0: f32, f1: f32, dur: f32, t: f32) -> f32 {
+    let ft = f0 + (f1 - f0) * (t / dur);
+    (TAU * ft * t).sin()
+}
+
+pub(super) fn noise(rng: &mut Rng) -> f32 {
+    rng.range(-1.0, 1.0)
+}
+
+pub(super) fn soft_clip(x: f32, drive: f32) -> f32 {
+    (x * drive).tanh()
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(super) enum Wave {
+    Sine { freq: f32 },
+    Saw { freq: f32 },
+    Chirp { from: f32, to: f32 },
+    // Repeatable white noise for a given seed.
+    Noise { seed: u64 },
+}
+
+// One voice of a spec, e.g. `{ "type": "sine", "freq": 72, "gain": 0.45, "decay_ms": 30 }`.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(super) struct Layer {
+    #[serde(flatten)]
+    pub(super) wave: Wave,
+    pub(super) gain: f32,
+    // Soft clip (tanh) with this much drive; omitted = clean.
+    #[serde(default)]
+    pub(super) drive: Option<f32>,
+    // Fades linearly to silence over this long from the start (a click or thump);
+    // omitted = lasts the whole sound.
+    #[serde(default)]
+    pub(super) decay_ms: Option<f32>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(super) struct SynthSpec {
+    pub(super) duration_ms: f32,
+    #[serde(default)]
+    pub(super) attack_ms: f32,
+    #[serde(default = "default_curve")]
+    pub(super) curve: f32,
+    pub(super) layers: Vec<Layer>,
+}
+
+fn default_curve() -> f32 {
+    1.0
+}
+
+impl SynthSpec {
+    // Reject specs from the frontend that are empty, too long or not numbers.
+    pub(super) fn validate(&self) -> Result<(), String> {
+        if !(self.duration_ms > 0.0 && self.duration_ms <= MAX_DURATION_MS) {
+            return Err(format!("synth duration_ms must be in 0..{MAX_DURATION_MS}"));
+        }
+        if self.layers.is_empty() || self.layers.len() > MAX_LAYERS {
+            return Err(format!("synth needs 1..{MAX_LAYERS} layers"));
+        }
+        let numbers = self.layers.iter().flat_map(|l| {
+            let wave = match l.wave {
+                Wave::Sine { freq } | Wave::Saw { freq } => vec![freq],
+                Wave::Chirp { from, to } => vec![from, to],
+                Wave::Noise { .. } => vec![],
+            };
+            wave.into_iter().chain([l.gain, l.drive.unwrap_or(1.0), l.decay_ms.unwrap_or(1.0)])
+        });
+        if !numbers.chain([self.attack_ms, self.curve]).all(f32::is_finite) {
+            return Err("synth spec has a value that isn't a number".to_string());
+        }
+        Ok(())
+    }
+
+    // Mono clip at `sr`: each layer's wave, clipped and decayed per layer, summed and
+    // shaped by the overall envelope.
+    pub(super) fn render(&self, sr: u32) -> AudioClip {
+        let dur = self.duration_ms / 1000.0;
+        let attack = (self.attack_ms / 1000.0).clamp(0.0, dur);
+        let curve = self.curve.max(0.0);
+        let n = (dur * sr as f32) as usize;
+        let mut rngs: Vec<Rng> = self
+            .layers
+            .iter()
+            .map(|l| Rng::seeded(if let Wave::Noise { seed } = l.wave { seed } else { 0 }))
+            .collect();
+        let mut out = Vec::with_capacity(n);
+
+        for i in 0..n {
+            let t = i as f32 / sr as f32;
+            let mut sum = 0.0;
+            for (layer, rng) in self.layers.iter().zip(&mut rngs) {
+                let mut x = match layer.wave {
+                    Wave::Sine { freq } => sine(freq, t),
+                    Wave::Saw { freq } => saw(freq, t),
+                    Wave::Chirp { from, to } => chirp(from, to, dur, t),
+                    Wave::Noise { .. } => noise(rng),
+                };
+                if let Some(drive) = layer.drive {
+                    x = soft_clip(x, drive);
+                }
+                if let Some(decay) = layer.decay_ms.map(|ms| ms / 1000.0) {
+                    x *= if t < decay { 1.0 - t / decay } else { 0.0 };
+                }
+                sum += x * layer.gain;
+            }
+            out.push(sum * envelope(t, attack, dur, curve));
+        }
+
+        AudioClip { channels: 1, sample_rate: sr, samples: out }
+    }
+
+    pub(super) fn to_buffer(&self, sr: u32) -> SamplesBuffer<f32> {
+        self.render(sr).into_source()
+    }
+}
+
+// A steady full-scale sine for `play_test_tone` (curve 0 = flat envelope).
+pub(super) fn test_tone(freq: f32, duration_ms: f32) -> SynthSpec {
+    SynthSpec {
+        duration_ms,
+        attack_ms: 0.0,
+        curve: 0.0,
+        layers: vec![Layer { wave: Wave::Sine { freq }, gain: 1.0, drive: None, decay_ms: None }],
+    }
+}